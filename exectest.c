@@ -0,0 +1,46 @@
+// exectest: checks what exec sets up.
+// Run without arguments, it checks that exec of a missing program fails and leaves it running, and then execs itself
+// with arguments and an environment. The second run checks them along with a bss that spans several pages.
+
+#include "types.h"
+#include "user.h"
+
+#define BSS_SIZE (3 * 4096 + 100)
+
+char bss[BSS_SIZE];
+
+void
+fail(char *message)
+{
+  printf(2, "exectest: %s\n", message);
+  exit(1);
+}
+
+int
+main(int argc, char *argv[], char *envp[])
+{
+  char *args[] = { "exectest", "one", "two", 0 };
+  char *env[] = { "TEST=exec", 0 };
+  int i;
+
+  if(argc == 1){
+    if(exec("/nonexistent", args, env) != -1)
+      fail("exec of a missing program did not fail");
+    exec("/exectest", args, env);
+    fail("exec failed");
+  }
+
+  if(argc != 3 || strcmp(argv[0], "exectest") != 0 || strcmp(argv[1], "one") != 0
+     || strcmp(argv[2], "two") != 0 || argv[3] != 0)
+    fail("bad argv");
+  if(envp[0] == 0 || strcmp(envp[0], "TEST=exec") != 0 || envp[1] != 0)
+    fail("bad envp");
+  for(i = 0; i < BSS_SIZE; i++){
+    if(bss[i] != 0)
+      fail("bss not zeroed");
+    bss[i] = i;
+  }
+
+  printf(1, "exectest: ok\n");
+  exit(0);
+}
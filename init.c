@@ -0,0 +1,76 @@
+// init: the first user program, which initcode execs.
+// Opens the console as standard input, output and error, checks what exec set up for it, runs the tests, and then
+// reaps orphaned children forever.
+
+#include "types.h"
+#include "user.h"
+#include "fcntl.h"
+
+char *tests[] = { "exectest", "mmaptest", 0 };
+char *envp[] = { "HOME=/", 0 };
+
+// In bss, which exec must have zeroed.
+int zeroed[1024];
+
+// Runs the test program path in a child and waits for it.
+// Returns the child's exit status, or -1 if it could not be run.
+int
+run(char *path)
+{
+  char *argv[2];
+  int pid, status;
+
+  argv[0] = path;
+  argv[1] = 0;
+  pid = fork();
+  if(pid < 0){
+    printf(2, "init: fork failed\n");
+    return -1;
+  }
+  if(pid == 0){
+    exec(path, argv, envp);
+    printf(2, "init: exec %s failed\n", path);
+    exit(1);
+  }
+  // Orphans may get reaped here too.
+  while(wait(&status) != pid)
+    ;
+  return status;
+}
+
+int
+main(int argc, char *argv[], char *env[])
+{
+  int i, failed;
+
+  if(open("console", O_RDWR) < 0)
+    exit(1);
+  dup(0);  // stdout
+  dup(0);  // stderr
+
+  printf(1, "init: starting\n");
+  if(argc != 1 || strcmp(argv[0], "/init") != 0 || argv[1] != 0)
+    printf(2, "init: bad argv\n");
+  if(env[0] == 0 || strcmp(env[0], "HOME=/") != 0 || env[1] != 0)
+    printf(2, "init: bad envp\n");
+  for(i = 0; i < sizeof(zeroed) / sizeof(zeroed[0]); i++){
+    if(zeroed[i] != 0){
+      printf(2, "init: bss not zeroed\n");
+      break;
+    }
+  }
+
+  failed = 0;
+  for(i = 0; tests[i]; i++){
+    if(run(tests[i]) != 0){
+      printf(2, "init: %s failed\n", tests[i]);
+      failed++;
+    }
+  }
+  printf(1, "init: %d of %d tests passed\n", i - failed, i);
+
+  for(;;){
+    if(wait(0) < 0)
+      sleep(100);
+  }
+}
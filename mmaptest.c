@@ -0,0 +1,120 @@
+// mmaptest: maps the file mmapdata and checks the pages that page faults fill in from it.
+// mmapdata is two pages and 100 bytes long, and byte i of it holds i % 251.
+
+#include "types.h"
+#include "user.h"
+#include "fcntl.h"
+#include "mman.h"
+
+#define PGSIZE 4096
+#define FILE_SIZE (2 * PGSIZE + 100)
+
+void
+fail(char *message)
+{
+  printf(2, "mmaptest: %s\n", message);
+  exit(1);
+}
+
+// Checks that p holds the file's bytes [offset, offset + n), or zeroes past the end of the file.
+void
+check(char *p, int offset, int n, char *message)
+{
+  int i;
+  uchar expected;
+
+  for(i = 0; i < n; i++){
+    expected = offset + i < FILE_SIZE ? (offset + i) % 251 : 0;
+    if((uchar)p[i] != expected)
+      fail(message);
+  }
+}
+
+// Reads the first n bytes of mmapdata into buffer.
+void
+read_file(char *buffer, int n)
+{
+  int fd;
+
+  if((fd = open("mmapdata", O_RDONLY)) < 0)
+    fail("open for reading failed");
+  if(read(fd, buffer, n) != n)
+    fail("read failed");
+  close(fd);
+}
+
+int
+main(int argc, char *argv[])
+{
+  char buffer[PGSIZE];
+  char *p, *q;
+  int fd, pid, status;
+
+  if((fd = open("mmapdata", O_RDWR)) < 0)
+    fail("open failed");
+
+  // A private read-only mapping of the whole file. The last page is only partly covered by the file.
+  p = mmap(0, 3 * PGSIZE, PROT_READ, MAP_PRIVATE, fd, 0);
+  if(p == MAP_FAILED)
+    fail("mmap failed");
+  check(p, 0, 3 * PGSIZE, "private mapping does not match the file");
+
+  // A mapping that starts at a file offset.
+  q = mmap(0, PGSIZE, PROT_READ, MAP_PRIVATE, fd, PGSIZE);
+  if(q == MAP_FAILED)
+    fail("mmap at an offset failed");
+  check(q, PGSIZE, PGSIZE, "mapping at an offset does not match the file");
+  if(munmap(q, PGSIZE) < 0)
+    fail("munmap failed");
+
+  if(mmap(0, PGSIZE, PROT_READ, MAP_PRIVATE, fd, 100) != MAP_FAILED)
+    fail("mmap at an unaligned offset did not fail");
+
+  // A child faults in the pages of a mapping it inherited but its parent never touched.
+  q = mmap(0, 2 * PGSIZE, PROT_READ, MAP_PRIVATE, fd, 0);
+  if(q == MAP_FAILED)
+    fail("mmap before fork failed");
+  pid = fork();
+  if(pid < 0)
+    fail("fork failed");
+  if(pid == 0){
+    check(q, 0, 2 * PGSIZE, "inherited mapping does not match the file");
+    exit(0);
+  }
+  if(wait(&status) != pid || status != 0)
+    fail("child failed");
+  munmap(q, 2 * PGSIZE);
+
+  // Writes to a private mapping stay out of the file.
+  q = mmap(0, PGSIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE, fd, 0);
+  if(q == MAP_FAILED)
+    fail("writable private mmap failed");
+  q[0] = 'x';
+  munmap(q, PGSIZE);
+  read_file(buffer, PGSIZE);
+  check(buffer, 0, PGSIZE, "write to a private mapping reached the file");
+
+  // Writes to a shared mapping reach the file when it is unmapped.
+  q = mmap(0, PGSIZE, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0);
+  if(q == MAP_FAILED)
+    fail("shared mmap failed");
+  q[1] = 'y';
+  munmap(q, PGSIZE);
+  read_file(buffer, PGSIZE);
+  if(buffer[1] != 'y')
+    fail("write to a shared mapping did not reach the file");
+  check(buffer + 2, 2, PGSIZE - 2, "shared mapping changed other bytes of the file");
+
+  munmap(p, 3 * PGSIZE);
+  close(fd);
+
+  // A read-only file can not be mapped shared and writable.
+  if((fd = open("mmapdata", O_RDONLY)) < 0)
+    fail("open read-only failed");
+  if(mmap(0, PGSIZE, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0) != MAP_FAILED)
+    fail("writable shared mmap of a read-only file did not fail");
+  close(fd);
+
+  printf(1, "mmaptest: ok\n");
+  exit(0);
+}
@@ -0,0 +1,154 @@
+//! # Address Spaces
+//! An AddressSpace owns the page directory of a user process, along with the user page tables and the references to
+//! the user pages it maps. The kernel half of every address space shares the kernel page directory's page tables, so
+//! only the user half [0, KERNEL_BASE) can be changed through it.
+//! Dropping an address space frees everything it owns. It must not be loaded in cr3 on any cpu at that point.
+
+use core::cmp;
+use x86::tlb;
+use memory_layout::{KERNEL_BASE, map_physical_virtual, map_virtual_to_physical};
+use mmu::{LARGE_PAGE_SIZE, page_directory_index, page_round_down, page_table_index, PAGE_SIZE, PTE_COPY_ON_WRITE};
+use page_allocator::FREE_PAGE_LIST;
+use paging::{self, NO_EXECUTE_BIT, PD, PDFlags, PT, PTFlags};
+use swap;
+use virtual_memory::{self, walk_page_directory};
+
+pub struct AddressSpace {
+  page_directory: &'static mut PD,
+}
+
+impl AddressSpace {
+  /// Creates an address space with an empty user half.
+  /// Returns None if there is no memory for the page directory, or if swap can not take another address space.
+  pub fn new() -> Option<AddressSpace> {
+    let page_directory = virtual_memory::setup_user_virtual_memory()?;
+    if !swap::add_address_space(page_directory) {
+      virtual_memory::free_virtual_memory(page_directory);
+      return None;
+    }
+    Some(AddressSpace { page_directory })
+  }
+
+  /// The page directory, for the helpers in virtual_memory, swap and vma that work on one.
+  pub fn page_directory(&mut self) -> &mut PD {
+    self.page_directory
+  }
+
+  /// The physical address of the page directory.
+  /// With PAE, cr3 holds the address of the cpu's page directory pointer table instead.
+  pub fn physical_address(&self) -> usize {
+    map_virtual_to_physical(&*self.page_directory as *const PD as usize)
+  }
+
+  /// Loads the address space on the current cpu.
+  /// Interrupts must be disabled, so that the process is not moved to another cpu halfway through.
+  pub fn activate(&self) {
+    swap::set_loaded_address_space(self.page_directory);
+    unsafe {
+      paging::load_page_directory(self.page_directory);
+    }
+  }
+
+  /// Maps [virtual_address, virtual_address + size) to the physical pages starting at physical_address.
+  /// The pages must come from the page allocator. The address space takes over one reference to each of them.
+  /// Returns false if the range is empty or reaches into the kernel half, or if there is no memory for the page
+  /// tables.
+  pub fn map(&mut self, virtual_address: usize, physical_address: usize, size: usize, permissions: PTFlags) -> bool {
+    match virtual_address.checked_add(size) {
+      Some(end) if size != 0 && end <= KERNEL_BASE => {}
+      _ => {
+        return false;
+      }
+    }
+    virtual_memory::map_pages(self.page_directory, virtual_address, size, physical_address, permissions)
+  }
+
+  /// Unmaps the user pages in [virtual_address, virtual_address + size) and drops a reference to each of them.
+  pub fn unmap(&mut self, virtual_address: usize, size: usize) {
+    let end = cmp::min(virtual_address.saturating_add(size), KERNEL_BASE);
+    virtual_memory::release_user_pages(self.page_directory, page_round_down(virtual_address), end);
+  }
+
+  /// Returns the physical address that virtual_address maps to, or None if its page is not present.
+  pub fn translate(&self, virtual_address: usize) -> Option<usize> {
+    let page_directory_entry = self.page_directory[page_directory_index(virtual_address)];
+    if !page_directory_entry.is_present() {
+      return None;
+    }
+    if page_directory_entry.flags().contains(PDFlags::PS) {
+      // A large page of the kernel half.
+      return Some(page_directory_entry.address().as_usize() + virtual_address % LARGE_PAGE_SIZE);
+    }
+
+    let page_table = unsafe { &*(map_physical_virtual(page_directory_entry.address().as_usize()) as *const PT) };
+    let page_table_entry = page_table[page_table_index(virtual_address)];
+    if !page_table_entry.is_present() {
+      return None;
+    }
+    Some(page_table_entry.address().as_usize() + virtual_address % PAGE_SIZE)
+  }
+
+  /// Changes the permissions of the mapped and swapped out user pages in [virtual_address, virtual_address + size).
+  /// permissions may contain PTFlags::RW, PTFlags::US and paging::no_execute(). Unless shared is set, as it is for
+  /// the pages of a shared mapping, a page that fork still shares with another address space stays read-only and
+  /// becomes copy-on-write instead of writable.
+  pub fn protect(&mut self, virtual_address: usize, size: usize, permissions: PTFlags, shared: bool) {
+    let end = cmp::min(virtual_address.saturating_add(size), KERNEL_BASE);
+    let mut address = page_round_down(virtual_address);
+    while address < end {
+      if let Some(page_table_entry) = walk_page_directory(self.page_directory, address, false) {
+        let present = page_table_entry.is_present();
+        if present || swap::is_swapped(page_table_entry) {
+          let mut bits = page_table_entry.0 & !(PTFlags::RW.bits() | PTFlags::US.bits() | PTE_COPY_ON_WRITE | NO_EXECUTE_BIT);
+          bits |= permissions.bits() & (PTFlags::US.bits() | NO_EXECUTE_BIT);
+          if permissions.contains(PTFlags::RW) {
+            let exclusive = present
+              && FREE_PAGE_LIST.reference_count(map_physical_virtual(page_table_entry.address().as_usize())) == 1;
+            if shared || exclusive {
+              bits |= PTFlags::RW.bits();
+            } else {
+              bits |= PTE_COPY_ON_WRITE;
+            }
+          }
+
+          page_table_entry.0 = bits;
+          unsafe {
+            tlb::flush(address);
+          }
+        }
+      }
+      address += PAGE_SIZE;
+    }
+  }
+
+  /// Grows the user memory from old_size to new_size with zeroed pages.
+  /// See virtual_memory::allocate_user_virtual_memory.
+  pub fn allocate(&mut self, old_size: usize, new_size: usize) -> Option<usize> {
+    virtual_memory::allocate_user_virtual_memory(self.page_directory, old_size, new_size)
+  }
+
+  /// Shrinks the user memory from old_size to new_size.
+  /// See virtual_memory::deallocate_user_virtual_memory.
+  pub fn deallocate(&mut self, old_size: usize, new_size: usize) -> usize {
+    virtual_memory::deallocate_user_virtual_memory(self.page_directory, old_size, new_size)
+  }
+
+  /// Creates a copy-on-write copy of the user memory [0, size) and the stack for fork.
+  /// See virtual_memory::copy_on_write_user_virtual_memory.
+  pub fn copy_on_write(&mut self, size: usize) -> Option<AddressSpace> {
+    let page_directory = virtual_memory::copy_on_write_user_virtual_memory(self.page_directory, size)?;
+    Some(AddressSpace { page_directory })
+  }
+
+  /// Maps this address space's user pages in [start, end) into child at the same addresses.
+  /// See virtual_memory::share_user_pages.
+  pub fn share(&mut self, child: &mut AddressSpace, start: usize, end: usize, copy_on_write: bool) -> bool {
+    virtual_memory::share_user_pages(self.page_directory, child.page_directory, start, end, copy_on_write)
+  }
+}
+
+impl Drop for AddressSpace {
+  fn drop(&mut self) {
+    virtual_memory::free_virtual_memory(self.page_directory);
+  }
+}
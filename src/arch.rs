@@ -1,28 +1,28 @@
 #[repr(C)]
 pub struct TrapFrame {
-    edi: u32,
-    esi: u32,
-    ebp: u32,
-    oesp: u32,
-    ebx: u32,
-    edx: u32,
-    ecx: u32,
-    eax: u32,
-    gs: u16,
+    pub edi: u32,
+    pub esi: u32,
+    pub ebp: u32,
+    pub oesp: u32,
+    pub ebx: u32,
+    pub edx: u32,
+    pub ecx: u32,
+    pub eax: u32,
+    pub gs: u16,
     padding1: u16,
-    fs: u16,
+    pub fs: u16,
     padding2: u16,
-    es: u16,
+    pub es: u16,
     padding3: u16,
-    ds: u16,
+    pub ds: u16,
     padding4: u16,
-    trapno: u32,
-    err: u32,
-    eip: u32,
-    cs: u16,
+    pub trapno: u32,
+    pub err: u32,
+    pub eip: u32,
+    pub cs: u16,
     padding5: u16,
-    eflags: u32,
-    esp: u32,
-    ss: u16,
+    pub eflags: u32,
+    pub esp: u32,
+    pub ss: u16,
     padding6: u16,
 }
@@ -0,0 +1,80 @@
+//! # Boot Options
+//! Options on the kernel command line, for example `multiboot /boot/kernel nosmap` in a GRUB menu entry or
+//! `-append nosmap` with qemu -kernel.
+//! entry.S saves the magic value and the address of the information a multiboot boot loader passes, and init copies
+//! the command line from that information. The options are the words of the command line. bootasm.S passes no
+//! command line, so no option is set when the kernel is started by it.
+//!
+//! Options:
+//! * nosmep - Do not enable SMEP, which keeps the kernel from executing user pages.
+//! * nosmap - Do not enable SMAP, which keeps the kernel from accessing user pages outside of user access windows.
+
+use core::mem;
+use memory_layout::map_physical_virtual;
+use page_allocator::ENTRY_MEMORY_TOP;
+
+/// The value a multiboot boot loader passes in eax.
+const MULTIBOOT_BOOTLOADER_MAGIC: u32 = 0x2BADB002;
+/// The flag of the multiboot information that says cmdline is valid.
+const MULTIBOOT_INFO_CMDLINE: u32 = 1 << 2;
+
+/// The longest command line that is kept.
+const COMMAND_LINE_SIZE: usize = 256;
+
+extern "C" {
+  static multiboot_magic: u32;
+  static multiboot_info: u32;
+}
+
+/// The start of the multiboot information.
+#[repr(C)]
+struct MultibootInfo {
+  flags: u32,
+  mem_lower: u32,
+  mem_upper: u32,
+  boot_device: u32,
+  /// The physical address of the null terminated command line.
+  cmdline: u32
+}
+
+static mut COMMAND_LINE: [u8; COMMAND_LINE_SIZE] = [0; COMMAND_LINE_SIZE];
+static mut COMMAND_LINE_LENGTH: usize = 0;
+
+/// Copies the command line.
+/// Must be called before page_allocator::init, which may hand out the memory the boot loader left it in. Only the
+/// memory below ENTRY_MEMORY_TOP is read, since the entry page directory maps nothing above it.
+pub fn init() {
+  let (magic, info) = unsafe { (multiboot_magic, multiboot_info as usize) };
+  if magic != MULTIBOOT_BOOTLOADER_MAGIC || info == 0
+    || info + mem::size_of::<MultibootInfo>() > ENTRY_MEMORY_TOP {
+    return;
+  }
+
+  let info = unsafe { &*(map_physical_virtual(info) as *const MultibootInfo) };
+  if info.flags & MULTIBOOT_INFO_CMDLINE == 0 {
+    return;
+  }
+
+  let mut address = info.cmdline as usize;
+  unsafe {
+    while COMMAND_LINE_LENGTH < COMMAND_LINE_SIZE && address < ENTRY_MEMORY_TOP {
+      let byte = *(map_physical_virtual(address) as *const u8);
+      if byte == 0 {
+        break;
+      }
+      COMMAND_LINE[COMMAND_LINE_LENGTH] = byte;
+      COMMAND_LINE_LENGTH += 1;
+      address += 1;
+    }
+  }
+}
+
+/// The command line, or an empty one if the boot loader passed none.
+pub fn command_line() -> &'static [u8] {
+  unsafe { &COMMAND_LINE[..COMMAND_LINE_LENGTH] }
+}
+
+/// Returns true if option is one of the words of the command line.
+pub fn is_set(option: &str) -> bool {
+  command_line().split(|&byte| byte == b' ').any(|word| word == option.as_bytes())
+}
@@ -4,16 +4,25 @@
 extern crate spin;
 use spin::Mutex;
 use core::fmt;
+use interrupt_controller;
+use page_allocator;
+use process;
+use slab;
+use traps::IRQ_KBD;
 
 mod uart;
 mod vga;
 
 use self::vga::VgaWriter;
 use self::uart::UartWriter;
+pub use self::uart::uart_interrupt;
 
 const BACKSPACE: i32 = 0x100;
 const BACKSCHAR: u8 = b'\x08';
 
+/// Control-P, which prints a process listing and the kernel's memory use.
+const CONTROL_P: i32 = b'P' as i32 - b'@' as i32;
+
 lazy_static! {
     static ref LOCK: Mutex<i32> = Mutex::new(0);
     pub static ref UART_CONSOLE: spin::Mutex<UartWriter> = spin::Mutex::new(UartWriter::new());
@@ -48,8 +57,45 @@ pub fn print(args: fmt::Arguments) {
   VGA_CONSOLE.lock().write_fmt(args).unwrap();
 }
 
+/// Writes buffer to the screen for a write to the console device, like consolewrite.
+/// Bytes that are not ASCII are written as '?'.
+pub fn write(buffer: &[u8]) {
+  for &byte in buffer {
+    print!("{}", if byte.is_ascii() { byte as char } else { '?' });
+  }
+}
+
 pub fn switch_to_virtual_memory() {
   VGA_CONSOLE.lock().switch_to_virtual_memory();
 }
 
-pub fn console_interrupt(c: fn() -> i32) {}
\ No newline at end of file
+/// Routes the keyboard and serial port interrupts to the first cpu.
+/// Must be called after interrupt_controller::init.
+pub fn init() {
+  unsafe {
+    interrupt_controller::enable(IRQ_KBD, 0);
+  }
+  uart::enable_interrupts();
+}
+
+/// Handles the input of a keyboard or serial port interrupt. get_character returns the next character, 0 for a key
+/// that produces none, or -1 once there is no more input.
+/// Control-P prints a process listing, like procdump, followed by the counters of the kernel object caches and the
+/// page allocator's pages. Nothing reads the console yet, so other input is dropped.
+pub fn console_interrupt(get_character: fn() -> i32) {
+  let mut dump = false;
+  loop {
+    match get_character() {
+      c if c < 0 => break,
+      CONTROL_P => dump = true,
+      _ => {}
+    }
+  }
+
+  // Print once all input is read, since printing takes a while.
+  if dump {
+    process::dump();
+    slab::dump();
+    page_allocator::dump();
+  }
+}
\ No newline at end of file
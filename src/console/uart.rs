@@ -3,6 +3,8 @@
 
 use core::fmt;
 use console::{BACKSCHAR, BACKSPACE, console_interrupt};
+use interrupt_controller;
+use traps::IRQ_COM1;
 use x86::io::{inb, outb};
 
 const COM1: u16 = 0x3f8;
@@ -88,8 +90,6 @@ fn uart_init() {
         // enable interrupts.
         inb(COM1 + 2);
         inb(COM1 + 0);
-        // TODO: Reimplement
-        // ioapicenable(IRQ_COM1, 0);
 
         // Announce that we're here.
         for ch in b"xv6...\n" {
@@ -98,6 +98,15 @@ fn uart_init() {
     }
 }
 
+/// Routes the serial port's receive interrupts to the first cpu, if there is a serial port.
+pub fn enable_interrupts() {
+    unsafe {
+        if UART_PRESENT {
+            interrupt_controller::enable(IRQ_COM1, 0);
+        }
+    }
+}
+
 pub fn uart_put_char(c: i32) {
     unsafe {
         if !UART_PRESENT {
@@ -0,0 +1,120 @@
+//! # ELF
+//! The headers of ELF executables, with the layout of elf.h.
+//! exec reads the file header at the start of a program and the program headers it points to, and loads the
+//! PT_LOAD segments. Only 32-bit little endian x86 executables are accepted.
+
+use core::{mem, ptr};
+use file::Inode;
+
+/// "\x7FELF" in little endian.
+pub const ELF_MAGIC: u32 = 0x464C457F;
+
+// Identification bytes following the magic number.
+const ELF_CLASS_32: u8 = 1;
+const ELF_DATA_LITTLE_ENDIAN: u8 = 1;
+const ELF_VERSION_CURRENT: u8 = 1;
+
+/// An executable file, rather than a relocatable or shared object.
+const ELF_TYPE_EXECUTABLE: u16 = 2;
+/// Intel 80386.
+const ELF_MACHINE_386: u16 = 3;
+
+/// The most program headers exec looks at.
+const MAX_PROGRAM_HEADERS: u16 = 64;
+
+// Values for ProgramHeader::program_type.
+pub const ELF_PROG_LOAD: u32 = 1;
+
+// Flag bits for ProgramHeader::flags.
+pub const ELF_PROG_FLAG_EXEC: u32 = 1;
+pub const ELF_PROG_FLAG_WRITE: u32 = 2;
+pub const ELF_PROG_FLAG_READ: u32 = 4;
+
+/// The file header.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct ElfHeader {
+  magic: u32,
+  elf: [u8; 12],
+  file_type: u16,
+  machine: u16,
+  version: u32,
+  /// The address the program starts at.
+  pub entry: u32,
+  /// The file offset of the program headers.
+  program_header_offset: u32,
+  section_header_offset: u32,
+  flags: u32,
+  header_size: u16,
+  program_header_size: u16,
+  /// The number of program headers.
+  pub program_header_count: u16,
+  section_header_size: u16,
+  section_header_count: u16,
+  section_name_index: u16,
+}
+
+/// A program header, which describes a segment.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct ProgramHeader {
+  pub program_type: u32,
+  /// The file offset of the segment's data.
+  pub offset: u32,
+  /// The address the segment is loaded at.
+  pub virtual_address: u32,
+  physical_address: u32,
+  /// The number of bytes of the segment stored in the file.
+  pub file_size: u32,
+  /// The size of the segment in memory. The bytes past file_size are zeroed, which is where bss is.
+  pub memory_size: u32,
+  pub flags: u32,
+  align: u32,
+}
+
+impl ElfHeader {
+  /// Reads the file header at the start of inode.
+  /// Returns None if the file is not an x86 executable this kernel can load.
+  pub fn read(inode: &Inode) -> Option<ElfHeader> {
+    let header: ElfHeader = read_struct(inode, 0)?;
+    let valid = header.magic == ELF_MAGIC
+      && header.elf[0] == ELF_CLASS_32
+      && header.elf[1] == ELF_DATA_LITTLE_ENDIAN
+      && header.elf[2] == ELF_VERSION_CURRENT
+      && header.file_type == ELF_TYPE_EXECUTABLE
+      && header.machine == ELF_MACHINE_386
+      && header.version == ELF_VERSION_CURRENT as u32
+      && header.program_header_size as usize == mem::size_of::<ProgramHeader>()
+      && header.program_header_count <= MAX_PROGRAM_HEADERS;
+    if valid {
+      Some(header)
+    } else {
+      None
+    }
+  }
+
+  /// Reads the program header with index from inode.
+  /// Returns None if it can not be read.
+  pub fn program_header(&self, inode: &Inode, index: u16) -> Option<ProgramHeader> {
+    let offset = self.program_header_offset as usize + index as usize * mem::size_of::<ProgramHeader>();
+    read_struct(inode, offset)
+  }
+}
+
+impl ProgramHeader {
+  /// The end of the segment in memory, or None if it wraps around.
+  pub fn end(&self) -> Option<usize> {
+    (self.virtual_address as usize).checked_add(self.memory_size as usize)
+  }
+}
+
+/// Reads a T stored at offset in inode.
+/// Returns None if the file ends before it.
+fn read_struct<T: Copy>(inode: &Inode, offset: usize) -> Option<T> {
+  let mut buffer = [0u8; 64];
+  let size = mem::size_of::<T>();
+  if size > buffer.len() || inode.read(offset, &mut buffer[..size]) != size {
+    return None;
+  }
+  Some(unsafe { ptr::read_unaligned(buffer.as_ptr() as *const T) })
+}
@@ -0,0 +1,231 @@
+//! # Exec
+//! Replaces the memory of the current process with a program loaded from an ELF executable.
+//! The program is loaded into a new address space. The process keeps its old memory until everything is loaded, so a
+//! failed exec returns to the caller unchanged.
+//!
+//! The new user stack grows down from USER_STACK_TOP and starts out as:
+//! * esp + 0 - A fake return address.
+//! * esp + 4 - argc.
+//! * esp + 8 - argv, the address of argc argument pointers followed by a null pointer.
+//! * esp + 12 - envp, the address of the environment pointers followed by a null pointer.
+//!
+//! followed by the pointer arrays and the strings they point to.
+
+use core::{cmp, mem, slice};
+use address_space::AddressSpace;
+use elf::{ElfHeader, ELF_PROG_FLAG_EXEC, ELF_PROG_FLAG_WRITE, ELF_PROG_LOAD, ProgramHeader};
+use file::Inode;
+use fs;
+use memory_layout::{map_physical_virtual, map_virtual_to_physical, USER_HEAP_LIMIT, USER_STACK_LIMIT, USER_STACK_TOP};
+use mmu::{page_round_down, page_round_up, PAGE_SIZE};
+use page_allocator::{FREE_PAGE_LIST, PageOwner};
+use paging::{self, PTFlags};
+use process::my_process;
+use virtual_memory;
+use vma;
+
+/// The most arguments and environment strings exec passes.
+pub const MAX_ARGUMENTS: usize = 32;
+
+/// Runs the program at path with the arguments argv and the environment envp.
+/// Returns false, leaving the process unchanged, if the program can not be loaded.
+pub fn exec(path: &[u8], argv: &[&[u8]], envp: &[&[u8]]) -> bool {
+  if argv.len() > MAX_ARGUMENTS || envp.len() > MAX_ARGUMENTS {
+    return false;
+  }
+  let process = match my_process() {
+    Some(process) => process,
+    None => {
+      return false;
+    }
+  };
+
+  let inode = match fs::namei(path) {
+    Some(inode) => inode,
+    None => {
+      return false;
+    }
+  };
+  let loaded = load(inode);
+  inode.put();
+  let (mut address_space, entry, size) = match loaded {
+    Some(loaded) => loaded,
+    None => {
+      return false;
+    }
+  };
+
+  let stack_pointer = match setup_stack(&mut address_space, argv, envp) {
+    Some(stack_pointer) => stack_pointer,
+    None => {
+      return false;
+    }
+  };
+
+  // Commit to the new program.
+  let name = path.rsplit(|&byte| byte == b'/').next().unwrap_or(path);
+  let length = cmp::min(name.len(), process.name.len() - 1);
+  process.name = [0; 16];
+  process.name[..length].copy_from_slice(&name[..length]);
+
+  vma::unmap_all(process);
+  address_space.activate();
+  // The old address space is freed here, now that it is no longer loaded.
+  process.address_space = Some(address_space);
+  process.size = size;
+  unsafe {
+    (*process.trap_frame).eip = entry as u32;
+    (*process.trap_frame).esp = stack_pointer as u32;
+  }
+  true
+}
+
+/// Loads the executable in inode into a new address space.
+/// Returns the address space, the entry point and the end of the loaded segments, where the heap starts.
+fn load(inode: &Inode) -> Option<(AddressSpace, usize, usize)> {
+  let header = ElfHeader::read(inode)?;
+  let mut address_space = AddressSpace::new()?;
+
+  let mut size = 0;
+  for index in 0..header.program_header_count {
+    let program_header = header.program_header(inode, index)?;
+    if program_header.program_type != ELF_PROG_LOAD {
+      continue;
+    }
+
+    // Segments must start on a page and come in order, so that no two of them share a page. They must end below
+    // USER_HEAP_LIMIT, since the heap starts after them and the mmap areas and the kernel lie above it.
+    let start = program_header.virtual_address as usize;
+    let end = program_header.end()?;
+    if program_header.memory_size < program_header.file_size || start % PAGE_SIZE != 0 || start < size
+      || end > USER_HEAP_LIMIT {
+      return None;
+    }
+
+    // A gap before the segment stays unmapped. It lies below the heap, so a fault there maps a zeroed page.
+    let mut address = start;
+    while address < end {
+      if !load_page(&mut address_space, inode, &program_header, address) {
+        return None;
+      }
+      address += PAGE_SIZE;
+    }
+    size = end;
+  }
+
+  if size == 0 {
+    return None;
+  }
+  Some((address_space, header.entry as usize, page_round_up(size)))
+}
+
+/// Allocates the page of a segment at address, fills it with the segment's file data and zeroes, and maps it with
+/// the segment's permissions.
+/// The page is filled before it is mapped, since swap may evict a mapped page while the next one is allocated.
+/// Returns false if there is not enough memory or the file is shorter than the segment.
+fn load_page(address_space: &mut AddressSpace, inode: &Inode, program_header: &ProgramHeader, address: usize) -> bool {
+  let page = match FREE_PAGE_LIST.alloc_page(PageOwner::UserPage) {
+    Some(page) => page,
+    None => {
+      return false;
+    }
+  };
+  let contents = unsafe { slice::from_raw_parts_mut(page as *mut u8, PAGE_SIZE) };
+  contents.fill(0);
+
+  // The part of the page past the file data is bss and stays zeroed.
+  let segment_offset = address - program_header.virtual_address as usize;
+  let n = cmp::min((program_header.file_size as usize).saturating_sub(segment_offset), PAGE_SIZE);
+  let loaded = n == 0 || inode.read(program_header.offset as usize + segment_offset, &mut contents[..n]) == n;
+  if !loaded
+    || !address_space.map(address, map_virtual_to_physical(page), PAGE_SIZE, segment_permissions(program_header)) {
+    unsafe {
+      FREE_PAGE_LIST.dealloc_page(page);
+    }
+    return false;
+  }
+  true
+}
+
+/// The page permissions for a segment with the flags of program_header.
+fn segment_permissions(program_header: &ProgramHeader) -> PTFlags {
+  let mut permissions = PTFlags::US;
+  if program_header.flags & ELF_PROG_FLAG_WRITE != 0 {
+    permissions |= PTFlags::RW;
+  }
+  if program_header.flags & ELF_PROG_FLAG_EXEC == 0 {
+    permissions |= paging::no_execute();
+  }
+  permissions
+}
+
+/// Copies the strings and the argument and environment arrays to the top of the user stack of address_space.
+/// Returns the initial stack pointer, or None if they do not fit or there is not enough memory.
+fn setup_stack(address_space: &mut AddressSpace, argv: &[&[u8]], envp: &[&[u8]]) -> Option<usize> {
+  let mut stack_pointer = USER_STACK_TOP;
+
+  // The strings, each with a terminator and aligned to a word.
+  let mut pointers = [0u32; 2 * (MAX_ARGUMENTS + 1)];
+  let mut index = 0;
+  for strings in [argv, envp] {
+    for string in strings.iter() {
+      stack_pointer = stack_pointer.checked_sub(string.len() + 1)? & !(mem::size_of::<u32>() - 1);
+      if stack_pointer < USER_STACK_LIMIT
+        || !copy_out(address_space, stack_pointer, string)
+        || !copy_out(address_space, stack_pointer + string.len(), &[0]) {
+        return None;
+      }
+      pointers[index] = stack_pointer as u32;
+      index += 1;
+    }
+    // The null pointer that ends the array.
+    pointers[index] = 0;
+    index += 1;
+  }
+
+  // The fake return address, argc, argv, envp, and the two arrays.
+  let word = mem::size_of::<u32>();
+  stack_pointer = stack_pointer.checked_sub((4 + index) * word)?;
+  if stack_pointer < USER_STACK_LIMIT {
+    return None;
+  }
+  let argv_address = stack_pointer + 4 * word;
+  let envp_address = argv_address + (argv.len() + 1) * word;
+  let words = [0xFFFFFFFF, argv.len() as u32, argv_address as u32, envp_address as u32];
+  for (i, value) in words.iter().chain(pointers[..index].iter()).enumerate() {
+    if !copy_out(address_space, stack_pointer + i * word, &value.to_le_bytes()) {
+      return None;
+    }
+  }
+  Some(stack_pointer)
+}
+
+/// Copies data to address in the user stack of address_space, mapping zeroed stack pages as needed.
+/// Returns false if there is not enough memory for a page.
+fn copy_out(address_space: &mut AddressSpace, address: usize, data: &[u8]) -> bool {
+  let mut done = 0;
+  while done < data.len() {
+    let user_address = address + done;
+    let physical_address = match address_space.translate(user_address) {
+      Some(physical_address) => physical_address,
+      None => {
+        if !virtual_memory::map_zeroed_page(address_space.page_directory(), page_round_down(user_address)) {
+          return false;
+        }
+        match address_space.translate(user_address) {
+          Some(physical_address) => physical_address,
+          None => {
+            return false;
+          }
+        }
+      }
+    };
+
+    let n = cmp::min(data.len() - done, PAGE_SIZE - user_address % PAGE_SIZE);
+    unsafe {
+      core::ptr::copy_nonoverlapping(data[done..].as_ptr(), map_physical_virtual(physical_address) as *mut u8, n);
+    }
+    done += n;
+  }
+  true
+}
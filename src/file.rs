@@ -1,5 +1,22 @@
-use fs::NDIRECT;
+use core::ptr::{null, null_mut};
+use console;
+use fs::{self, BSIZE, NDIRECT, NINDIRECT, T_DEV, T_DIR};
+use ide;
 use pipe::Pipe;
+use slab::Slab;
+
+// File types.
+pub const FD_NONE: i32 = 0;
+pub const FD_PIPE: i32 = 1;
+pub const FD_INODE: i32 = 2;
+
+/// The major device number of the console.
+pub const CONSOLE: i16 = 1;
+
+/// Cache of open file objects.
+pub static FILE_CACHE: Slab<File> = Slab::new("file", File::new);
+/// Cache of in-memory inodes.
+pub static INODE_CACHE: Slab<Inode> = Slab::new("inode", Inode::new);
 
 #[repr(C)]
 pub struct File {
@@ -8,7 +25,7 @@ pub struct File {
     readable: u8,
     writable: u8,
     pipe: *const Pipe,
-    ip: *const Inode,
+    ip: *mut Inode,
     off: u32,
 }
 
@@ -25,3 +42,277 @@ pub struct Inode {
     size: u32,
     addrs: [u32; NDIRECT+1],
 }
+
+impl File {
+    pub const fn new() -> File {
+        File {
+            itype: 0,
+            refc: 0,
+            readable: 0,
+            writable: 0,
+            pipe: null(),
+            ip: null_mut(),
+            off: 0,
+        }
+    }
+
+    /// Allocates a file with one reference from FILE_CACHE, like filealloc.
+    /// Returns None if there is no memory for it.
+    pub fn alloc() -> Option<&'static mut File> {
+        let file = FILE_CACHE.alloc()?;
+        file.refc = 1;
+        Some(file)
+    }
+
+    /// Opens inode, like the end of sys_open. The file takes over the caller's reference to inode.
+    /// Returns None if there is no memory for the file, in which case the reference is dropped.
+    pub fn open(inode: &'static mut Inode, readable: bool, writable: bool) -> Option<&'static mut File> {
+        let file = match File::alloc() {
+            Some(file) => file,
+            None => {
+                inode.put();
+                return None;
+            }
+        };
+        file.itype = FD_INODE;
+        file.ip = inode;
+        file.off = 0;
+        file.readable = readable as u8;
+        file.writable = writable as u8;
+        Some(file)
+    }
+
+    pub fn is_readable(&self) -> bool {
+        self.readable != 0
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.writable != 0
+    }
+
+    /// The offset the next read or write starts at.
+    pub fn offset(&self) -> u32 {
+        self.off
+    }
+
+    /// Moves the offset back to one returned by offset, for a system call that fails after it read.
+    pub fn set_offset(&mut self, offset: u32) {
+        self.off = offset;
+    }
+
+    /// Returns the inode of a file that refers to one, or None for pipes.
+    pub fn inode(&self) -> Option<&Inode> {
+        if self.itype == FD_INODE && !self.ip.is_null() {
+            unsafe { Some(&*self.ip) }
+        } else {
+            None
+        }
+    }
+
+    /// Adds a reference to the file, like filedup.
+    pub fn dup(&mut self) {
+        assert!(self.refc >= 1, "File::dup");
+        self.refc += 1;
+    }
+
+    /// Drops a reference to the file, like fileclose. The last reference drops the file's reference to its inode and
+    /// returns the file to FILE_CACHE.
+    pub fn close(&mut self) {
+        assert!(self.refc >= 1, "File::close");
+        self.refc -= 1;
+        if self.refc == 0 {
+            if self.itype == FD_INODE && !self.ip.is_null() {
+                unsafe {
+                    (*self.ip).put();
+                }
+            }
+            self.itype = FD_NONE;
+            self.ip = null_mut();
+            unsafe {
+                FILE_CACHE.dealloc(self);
+            }
+        }
+    }
+
+    /// Reads from the file at its offset into buffer and advances the offset, like fileread.
+    /// Returns the number of bytes read, or None if the file can not be read. Nothing reads the console yet, so
+    /// devices can not be read.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        if !self.is_readable() || self.itype != FD_INODE {
+            return None;
+        }
+        let inode = unsafe { &*self.ip };
+        if inode.itype == T_DEV {
+            return None;
+        }
+        let n = inode.read(self.off as usize, buffer);
+        self.off += n as u32;
+        Some(n)
+    }
+
+    /// Writes buffer to the file at its offset and advances the offset, like filewrite.
+    /// Writes to the console device go to the screen. Files do not grow, so a write stops at the end of the file.
+    /// Returns the number of bytes written, or None if the file can not be written.
+    pub fn write(&mut self, buffer: &[u8]) -> Option<usize> {
+        if !self.is_writable() || self.itype != FD_INODE {
+            return None;
+        }
+        let inode = unsafe { &*self.ip };
+        if inode.itype == T_DEV {
+            if inode.major != CONSOLE {
+                return None;
+            }
+            console::write(buffer);
+            return Some(buffer.len());
+        }
+        let n = inode.write(self.off as usize, buffer);
+        self.off += n as u32;
+        Some(n)
+    }
+}
+
+impl Inode {
+    pub const fn new() -> Inode {
+        Inode {
+            dev: 0,
+            inum: 0,
+            refc: 0,
+            valid: 0,
+            itype: 0,
+            major: 0,
+            nlink: 0,
+            size: 0,
+            addrs: [0; NDIRECT+1],
+        }
+    }
+
+    /// Allocates an in-memory inode with one reference from INODE_CACHE for inode number inum on disk dev.
+    /// Returns None if there is no memory for it.
+    pub fn alloc(dev: u32, inum: u32) -> Option<&'static mut Inode> {
+        let inode = INODE_CACHE.alloc()?;
+        inode.dev = dev;
+        inode.inum = inum;
+        inode.refc = 1;
+        Some(inode)
+    }
+
+    /// Allocates an in-memory inode for inode number inum on disk dev and reads it from disk, like iget followed by
+    /// ilock. Returns None if there is no memory for it or the inode is not in use.
+    pub fn get(dev: u32, inum: u32) -> Option<&'static mut Inode> {
+        let inode = Inode::alloc(dev, inum)?;
+        let disk_inode = match fs::read_disk_inode(dev, inum) {
+            Some(disk_inode) if disk_inode.itype != 0 => disk_inode,
+            _ => {
+                inode.put();
+                return None;
+            }
+        };
+        inode.itype = disk_inode.itype;
+        inode.major = disk_inode.major;
+        inode.nlink = disk_inode.nlink;
+        inode.size = disk_inode.size;
+        inode.addrs = disk_inode.addrs;
+        inode.valid = 1;
+        Some(inode)
+    }
+
+    pub fn dev(&self) -> u32 {
+        self.dev
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.itype == T_DIR
+    }
+
+    pub fn size(&self) -> usize {
+        self.size as usize
+    }
+
+    /// Adds a reference to the inode, like idup.
+    pub fn dup(&mut self) {
+        self.refc += 1;
+    }
+
+    /// Drops a reference to the inode, like iput. The last reference returns the inode to INODE_CACHE.
+    pub fn put(&mut self) {
+        assert!(self.refc >= 1, "Inode::put");
+        self.refc -= 1;
+        if self.refc == 0 {
+            unsafe {
+                INODE_CACHE.dealloc(self);
+            }
+        }
+    }
+
+    /// Returns the disk block that holds block number block_number of the file, or 0 if it has none.
+    /// Unlike bmap, this never allocates a block.
+    fn block_address(&self, block_number: usize) -> u32 {
+        if block_number < NDIRECT {
+            return self.addrs[block_number];
+        }
+
+        let index = block_number - NDIRECT;
+        if index >= NINDIRECT || self.addrs[NDIRECT] == 0 {
+            return 0;
+        }
+
+        let mut indirect = [0u8; BSIZE];
+        if !ide::read_sectors(self.dev as u8, self.addrs[NDIRECT], &mut indirect) {
+            return 0;
+        }
+        let entry = &indirect[index * 4..index * 4 + 4];
+        u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]])
+    }
+
+    /// Reads file data starting at offset into buffer, like readi.
+    /// Returns the number of bytes read, which is less than buffer.len() at the end of the file.
+    pub fn read(&self, offset: usize, buffer: &mut [u8]) -> usize {
+        if offset >= self.size() {
+            return 0;
+        }
+
+        let count = buffer.len().min(self.size() - offset);
+        let mut block = [0u8; BSIZE];
+        let mut done = 0;
+        while done < count {
+            let position = offset + done;
+            let block_offset = position % BSIZE;
+            let n = (BSIZE - block_offset).min(count - done);
+            let address = self.block_address(position / BSIZE);
+            if address == 0 || !ide::read_sectors(self.dev as u8, address, &mut block) {
+                break;
+            }
+            buffer[done..done + n].copy_from_slice(&block[block_offset..block_offset + n]);
+            done += n;
+        }
+        done
+    }
+
+    /// Writes buffer into the file starting at offset, like writei.
+    /// Only the existing blocks of the file are written, so the file never grows.
+    /// Returns the number of bytes written.
+    pub fn write(&self, offset: usize, buffer: &[u8]) -> usize {
+        if offset >= self.size() {
+            return 0;
+        }
+
+        let count = buffer.len().min(self.size() - offset);
+        let mut block = [0u8; BSIZE];
+        let mut done = 0;
+        while done < count {
+            let position = offset + done;
+            let block_offset = position % BSIZE;
+            let n = (BSIZE - block_offset).min(count - done);
+            let address = self.block_address(position / BSIZE);
+            if address == 0 || !ide::read_sectors(self.dev as u8, address, &mut block) {
+                break;
+            }
+            block[block_offset..block_offset + n].copy_from_slice(&buffer[done..done + n]);
+            if !ide::write_sectors(self.dev as u8, address, &block) {
+                break;
+            }
+            done += n;
+        }
+        done
+    }
+}
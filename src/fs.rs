@@ -1 +1,189 @@
+//! # File System
+//! Looks up files in the xv6 file system on ide::ROOT_DISK, which mkfs builds from the user programs.
+//! The disk layout is the one of fs.h: the boot block, the superblock, the log, the inodes, the free block bitmap and
+//! then the data blocks. The kernel only reads the inodes and directories. File data is read and written in place
+//! through the inode, so there is no need for the log.
+
+use core::cmp;
+use file::Inode;
+use ide::{self, ROOT_DISK};
+use process::my_process;
+
 pub const NDIRECT: usize = 12;
+
+/// The block size of the file system. A block is one disk sector.
+pub const BSIZE: usize = 512;
+/// The number of block addresses in the indirect block.
+pub const NINDIRECT: usize = BSIZE / 4;
+
+/// The inode number of the root directory.
+pub const ROOTINO: u32 = 1;
+/// The longest name of a directory entry. Shorter names are null terminated.
+pub const DIRSIZ: usize = 14;
+
+// Inode types.
+pub const T_DIR: i16 = 1;
+pub const T_FILE: i16 = 2;
+pub const T_DEV: i16 = 3;
+
+/// The size of an on-disk inode: type, major, minor and nlink as 16-bit values, the size, and the block addresses.
+const DINODE_SIZE: usize = 4 * 2 + 4 + (NDIRECT + 1) * 4;
+/// Inodes per block.
+const IPB: usize = BSIZE / DINODE_SIZE;
+/// The size of a directory entry: the inode number as a 16-bit value and the name.
+const DIRENT_SIZE: usize = 2 + DIRSIZ;
+
+/// The superblock, which describes the disk layout. It is the second block of the disk.
+#[derive(Copy, Clone)]
+pub struct SuperBlock {
+    /// The size of the file system image in blocks.
+    pub size: u32,
+    pub nblocks: u32,
+    pub ninodes: u32,
+    pub nlog: u32,
+    pub logstart: u32,
+    /// The block of the first inode.
+    pub inodestart: u32,
+    pub bmapstart: u32,
+}
+
+/// An inode as it is stored on disk.
+#[derive(Copy, Clone)]
+pub struct DiskInode {
+    /// The inode type, or 0 if the inode is free.
+    pub itype: i16,
+    pub major: i16,
+    pub minor: i16,
+    pub nlink: i16,
+    pub size: u32,
+    pub addrs: [u32; NDIRECT+1],
+}
+
+/// The superblock of ROOT_DISK, or None if it has no file system.
+static mut SUPERBLOCK: Option<SuperBlock> = None;
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+/// Reads the superblock of ROOT_DISK, like readsb. Paths can only be looked up if this finds a file system.
+pub fn init() {
+    let mut block = [0u8; BSIZE];
+    if !ide::has_disk(ROOT_DISK) || !ide::read_sectors(ROOT_DISK, 1, &mut block) {
+        println!("fs: no file system disk");
+        return;
+    }
+
+    let superblock = SuperBlock {
+        size: read_u32(&block, 0),
+        nblocks: read_u32(&block, 4),
+        ninodes: read_u32(&block, 8),
+        nlog: read_u32(&block, 12),
+        logstart: read_u32(&block, 16),
+        inodestart: read_u32(&block, 20),
+        bmapstart: read_u32(&block, 24),
+    };
+    if superblock.ninodes == 0 || superblock.inodestart == 0 || superblock.inodestart >= superblock.size {
+        println!("fs: no file system on disk {}", ROOT_DISK);
+        return;
+    }
+
+    println!("fs: size {} nblocks {} ninodes {} inodestart {} bmap start {}", superblock.size, superblock.nblocks,
+             superblock.ninodes, superblock.inodestart, superblock.bmapstart);
+    unsafe {
+        SUPERBLOCK = Some(superblock);
+    }
+}
+
+/// Reads inode inum from disk dev.
+/// Returns None if there is no file system, inum is out of range or the disk read fails.
+pub fn read_disk_inode(dev: u32, inum: u32) -> Option<DiskInode> {
+    let superblock = unsafe { SUPERBLOCK }?;
+    if dev != ROOT_DISK as u32 || inum >= superblock.ninodes {
+        return None;
+    }
+
+    let mut block = [0u8; BSIZE];
+    if !ide::read_sectors(ROOT_DISK, superblock.inodestart + inum / IPB as u32, &mut block) {
+        return None;
+    }
+
+    let bytes = &block[(inum as usize % IPB) * DINODE_SIZE..][..DINODE_SIZE];
+    let mut addrs = [0; NDIRECT+1];
+    for (i, address) in addrs.iter_mut().enumerate() {
+        *address = read_u32(bytes, 12 + 4 * i);
+    }
+    Some(DiskInode {
+        itype: read_u16(bytes, 0) as i16,
+        major: read_u16(bytes, 2) as i16,
+        minor: read_u16(bytes, 4) as i16,
+        nlink: read_u16(bytes, 6) as i16,
+        size: read_u32(bytes, 8),
+        addrs,
+    })
+}
+
+/// Looks for the entry name in the directory dir, like dirlookup.
+/// Returns the inode number of the entry, or None if there is none.
+fn directory_lookup(dir: &Inode, name: &[u8]) -> Option<u32> {
+    let mut entry = [0u8; DIRENT_SIZE];
+    let mut offset = 0;
+    while offset + DIRENT_SIZE <= dir.size() {
+        if dir.read(offset, &mut entry) != DIRENT_SIZE {
+            return None;
+        }
+        offset += DIRENT_SIZE;
+
+        let inum = read_u16(&entry, 0) as u32;
+        if inum == 0 {
+            continue;
+        }
+        let entry_name = &entry[2..];
+        let length = entry_name.iter().position(|&byte| byte == 0).unwrap_or(DIRSIZ);
+        if &entry_name[..length] == name {
+            return Some(inum);
+        }
+    }
+    None
+}
+
+/// Splits the next path element off path, like skipelem.
+/// Returns the element, truncated to DIRSIZ bytes, and the rest of the path, or None if there are no elements left.
+fn skip_element(path: &[u8]) -> Option<(&[u8], &[u8])> {
+    let start = path.iter().position(|&byte| byte != b'/')?;
+    let path = &path[start..];
+    let end = path.iter().position(|&byte| byte == b'/').unwrap_or(path.len());
+    Some((&path[..cmp::min(end, DIRSIZ)], &path[end..]))
+}
+
+/// Looks up the inode for path, like namei, and returns it with a reference the caller drops with Inode::put.
+/// Relative paths start at the current process's working directory, or at the root if it has none.
+/// Returns None if there is no such file.
+pub fn namei(path: &[u8]) -> Option<&'static mut Inode> {
+    let cwd = match my_process() {
+        Some(process) if path.first() != Some(&b'/') && !process.cwd.is_null() => process.cwd,
+        _ => core::ptr::null_mut(),
+    };
+    let mut inode = if cwd.is_null() {
+        Inode::get(ROOT_DISK as u32, ROOTINO)?
+    } else {
+        unsafe {
+            (*cwd).dup();
+            &mut *cwd
+        }
+    };
+
+    let mut rest = path;
+    while let Some((name, next)) = skip_element(rest) {
+        rest = next;
+        let inum = if inode.is_directory() { directory_lookup(inode, name) } else { None };
+        let dev = inode.dev();
+        inode.put();
+        inode = Inode::get(dev, inum?)?;
+    }
+    Some(inode)
+}
@@ -1,4 +1,5 @@
-use x86::io::{inb, outb};
+use x86::io::{inb, inl, outb, outl};
+use spin::Mutex;
 use acpi::MAX_CPUS;
 use interrupt_controller;
 use traps::IRQ_IDE;
@@ -9,37 +10,73 @@ pub const IDE_DRIVE_READY: u8 = 0x40;
 pub const IDE_DRIVE_WRITE_FAULT: u8 = 0x20;
 pub const IDE_ERROR: u8 = 0x01;
 
-static mut HAS_DISK_1: bool = false;
+pub const IDE_CMD_READ: u8 = 0x20;
+pub const IDE_CMD_WRITE: u8 = 0x30;
 
-pub fn init() {
-  let i: usize;
+/// Device control bit that stops the disk from raising interrupts. Transfers are polled instead.
+const IDE_NO_INTERRUPTS: u8 = 0x02;
+
+/// The disks of the primary and the secondary channel, master and slave each.
+const DISK_COUNT: usize = 4;
+
+/// The disk holding the file system, xv6's ROOTDEV. Disk 0 is the boot disk with the kernel.
+pub const ROOT_DISK: u8 = 1;
+/// The disk used as swap space, the master of the secondary channel.
+pub const SWAP_DISK: u8 = 2;
+
+/// The command block and control ports of the primary and the secondary channel.
+const COMMAND_PORTS: [u16; 2] = [0x1f0, 0x170];
+const CONTROL_PORTS: [u16; 2] = [0x3f6, 0x376];
+
+static mut HAS_DISK: [bool; DISK_COUNT] = [true, false, false, false];
 
+/// Serializes access to the disk controller's ports.
+static IDE_LOCK: Mutex<()> = Mutex::new(());
+
+pub fn init() {
   unsafe {
     interrupt_controller::enable(IRQ_IDE, (MAX_CPUS - 1) as u32);
   }
-  wait(false);
+  wait(0, false);
 
   unsafe {
-    outb(0x1f6u16, 0xe0 | (1u8 << 4));
+    for disk in 1..DISK_COUNT as u8 {
+      let port = command_port(disk);
+      outb(port + 6, 0xe0 | ((disk & 1) << 4));
 
-    for i in 0..1000 {
-      if inb(0x1f7) != 0 {
-        HAS_DISK_1 = true;
-        break;
+      // A channel without any disk reads as 0, or as 0xff if it is not there at all.
+      for _ in 0..1000 {
+        let status = inb(port + 7);
+        if status != 0 && status != 0xff {
+          HAS_DISK[disk as usize] = true;
+          break;
+        }
       }
-    }
 
-    // Switch back to disk 0.
-    outb(0x1f6, 0xe0 | (0 << 4));
+      // Switch back to the master of the channel.
+      outb(port + 6, 0xe0 | (0 << 4));
+    }
   }
 }
 
-fn wait(check_error: bool) -> bool {
+/// Returns true if disk is attached.
+pub fn has_disk(disk: u8) -> bool {
+  (disk as usize) < DISK_COUNT && unsafe { HAS_DISK[disk as usize] }
+}
+
+/// The first command block port of the channel disk is on.
+fn command_port(disk: u8) -> u16 {
+  COMMAND_PORTS[(disk >> 1) as usize]
+}
+
+/// Waits for the disk to become ready.
+/// Returns true if check_error is set and the disk reported an error.
+fn wait(disk: u8, check_error: bool) -> bool {
   let mut result: u8;
   unsafe {
     loop {
-      result = inb(0x01f7u16);
-      if result & (IDE_BUSY | IDE_DRIVE_READY) != IDE_DRIVE_READY {
+      result = inb(command_port(disk) + 7);
+      if result & (IDE_BUSY | IDE_DRIVE_READY) == IDE_DRIVE_READY {
         break;
       }
     }
@@ -50,4 +87,65 @@ fn wait(check_error: bool) -> bool {
     false
   }
 
-}
\ No newline at end of file
+}
+
+/// Sends a command for count sectors starting at sector.
+unsafe fn start_transfer(disk: u8, sector: u32, count: u8, command: u8) {
+  let port = command_port(disk);
+  wait(disk, false);
+  outb(CONTROL_PORTS[(disk >> 1) as usize], IDE_NO_INTERRUPTS);
+  outb(port + 2, count);
+  outb(port + 3, (sector & 0xff) as u8);
+  outb(port + 4, ((sector >> 8) & 0xff) as u8);
+  outb(port + 5, ((sector >> 16) & 0xff) as u8);
+  outb(port + 6, 0xe0 | ((disk & 1) << 4) | ((sector >> 24) & 0x0f) as u8);
+  outb(port + 7, command);
+}
+
+/// Reads buffer.len() / SECTOR_SIZE sectors starting at sector, polling the disk until the data is ready.
+/// Returns false if the disk is not attached or reported an error.
+pub fn read_sectors(disk: u8, sector: u32, buffer: &mut [u8]) -> bool {
+  let _lock = IDE_LOCK.lock();
+  let count = buffer.len() / SECTOR_SIZE as usize;
+  assert!(count > 0 && count <= 255 && buffer.len() % SECTOR_SIZE as usize == 0);
+  if !has_disk(disk) {
+    return false;
+  }
+
+  unsafe {
+    start_transfer(disk, sector, count as u8, IDE_CMD_READ);
+    for sector_buffer in buffer.chunks_mut(SECTOR_SIZE as usize) {
+      if wait(disk, true) {
+        return false;
+      }
+      for word in sector_buffer.chunks_mut(4) {
+        word.copy_from_slice(&inl(command_port(disk)).to_le_bytes());
+      }
+    }
+  }
+  true
+}
+
+/// Writes buffer.len() / SECTOR_SIZE sectors starting at sector and waits for the disk to finish.
+/// Returns false if the disk is not attached or reported an error.
+pub fn write_sectors(disk: u8, sector: u32, buffer: &[u8]) -> bool {
+  let _lock = IDE_LOCK.lock();
+  let count = buffer.len() / SECTOR_SIZE as usize;
+  assert!(count > 0 && count <= 255 && buffer.len() % SECTOR_SIZE as usize == 0);
+  if !has_disk(disk) {
+    return false;
+  }
+
+  unsafe {
+    start_transfer(disk, sector, count as u8, IDE_CMD_WRITE);
+    for sector_buffer in buffer.chunks(SECTOR_SIZE as usize) {
+      if wait(disk, true) {
+        return false;
+      }
+      for word in sector_buffer.chunks(4) {
+        outl(command_port(disk), u32::from_le_bytes([word[0], word[1], word[2], word[3]]));
+      }
+    }
+    !wait(disk, true)
+  }
+}
@@ -46,7 +46,25 @@ impl Idt {
   }
 
   pub fn set_handler(&mut self, entry: u8, handler: HandlerFunc) -> &mut GateDescriptorOptions {
-    self.0[entry as usize] = GateDescriptor::new(InterruptGate32, segmentation::cs(), handler);
+    self.0[entry as usize] = GateDescriptor::new(InterruptGate32, segmentation::cs(), handler as u32);
+    &mut self.0[entry as usize].options
+  }
+
+  /// Sets the handler for an exception that pushes an error code, like a page fault.
+  pub fn set_handler_with_error_code(&mut self, entry: u8, handler: HandlerFuncWithErrorCode) -> &mut GateDescriptorOptions {
+    self.0[entry as usize] = GateDescriptor::new(InterruptGate32, segmentation::cs(), handler as u32);
+    &mut self.0[entry as usize].options
+  }
+
+  /// Sets the handler for a vector whose entry point is written in assembly, like the system call vector.
+  pub fn set_handler_address(&mut self, entry: u8, handler_address: u32) -> &mut GateDescriptorOptions {
+    self.0[entry as usize] = GateDescriptor::new(InterruptGate32, segmentation::cs(), handler_address);
+    &mut self.0[entry as usize].options
+  }
+
+  /// Makes a vector switch to the task whose task state segment is selected by task_state.
+  pub fn set_task_gate(&mut self, entry: u8, task_state: SegmentSelector) -> &mut GateDescriptorOptions {
+    self.0[entry as usize] = GateDescriptor::new(SystemDescriptorTypes32::TaskGate, task_state, 0);
     &mut self.0[entry as usize].options
   }
 
@@ -61,6 +79,7 @@ impl Idt {
 }
 
 pub type HandlerFunc = extern "x86-interrupt" fn(_: InterruptStackFrame);
+pub type HandlerFuncWithErrorCode = extern "x86-interrupt" fn(_: InterruptStackFrame, error_code: u32);
 
 #[derive(Debug)]
 #[repr(C)]
@@ -74,8 +93,8 @@ pub struct InterruptStackFrame {
 
 impl GateDescriptor {
 
-  fn new(gate_type: SystemDescriptorTypes32, gdt_selector: SegmentSelector, handler: HandlerFunc) -> Self {
-    let ptr = handler as u32;
+  fn new(gate_type: SystemDescriptorTypes32, gdt_selector: SegmentSelector, handler_address: u32) -> Self {
+    let ptr = handler_address;
     Self {
       pointer_low: ptr as u16,
       gdt_selector,
@@ -105,7 +124,7 @@ impl GateDescriptorOptions {
 
   pub fn set_privilege_level(&mut self, descriptor_privilege_level: u8) -> &mut Self {
     self.bits &= 0x9F;
-    self.bits |= (descriptor_privilege_level & 0x02) << 4;
+    self.bits |= (descriptor_privilege_level & 0x03) << 5;
     self
   }
 
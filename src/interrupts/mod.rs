@@ -1,15 +1,46 @@
 //! # Interrupts
 //! Handles interrupts.
 
+use x86::controlregs;
+use x86::bits32::eflags::EFlags;
+use x86::Ring;
+use x86::segmentation::SegmentSelector;
 use interrupts::idt::InterruptStackFrame;
+use kernel_stack;
+use memory_layout::KERNEL_BASE;
+use mmu::SEGMENT_DOUBLE_FAULT_TASK_STATE;
+use paging;
+use process::{self, Process};
+use swap;
+use trap::{keyboard_vector, spurious_vector, syscall_vector, timer_vector, uart_vector};
+use traps::{IRQ_COM1, IRQ_KBD, IRQ_SPURIOUS, IRQ_TIMER, T_IRQ0, T_SYSCALL};
+use virtual_memory;
+use vma;
 
 mod idt;
 
+// Page fault error code bits.
+/// The fault was caused by a protection violation rather than a page that is not present.
+pub const PAGE_FAULT_PROTECTION: u32 = 0x1;
+/// The fault was caused by a write.
+pub const PAGE_FAULT_WRITE: u32 = 0x2;
+/// The fault happened in user mode.
+pub const PAGE_FAULT_USER: u32 = 0x4;
+/// The fault was caused by an instruction fetch. Only reported with the no-execute bit or SMEP enabled.
+pub const PAGE_FAULT_INSTRUCTION_FETCH: u32 = 0x10;
+
 lazy_static! {
   static ref IDT: idt::Idt = {
     let mut idt = idt::Idt::new();
     idt.set_handler(0, divide_by_zero_handler);
-    idt.set_handler(14, page_fault_handler);
+    idt.set_task_gate(8, SegmentSelector::new(SEGMENT_DOUBLE_FAULT_TASK_STATE as u16, Ring::Ring0));
+    idt.set_handler_with_error_code(14, page_fault_handler);
+    // User code may raise the system call vector. Interrupts stay off while the call runs.
+    idt.set_handler_address(T_SYSCALL as u8, syscall_vector as u32).set_privilege_level(3);
+    idt.set_handler_address((T_IRQ0 + IRQ_TIMER) as u8, timer_vector as u32);
+    idt.set_handler_address((T_IRQ0 + IRQ_SPURIOUS) as u8, spurious_vector as u32);
+    idt.set_handler_address((T_IRQ0 + IRQ_KBD) as u8, keyboard_vector as u32);
+    idt.set_handler_address((T_IRQ0 + IRQ_COM1) as u8, uart_vector as u32);
     idt
   };
 }
@@ -22,15 +53,84 @@ extern "x86-interrupt" fn divide_by_zero_handler(exception_stack_frame: Interrup
 
 }
 
-extern "x86-interrupt" fn page_fault_handler(exception_stack_frame: InterruptStackFrame) {
-  println!("Page fault!");
+extern "x86-interrupt" fn page_fault_handler(exception_stack_frame: InterruptStackFrame, error_code: u32) {
+  let fault_address = unsafe { controlregs::cr2() };
+
+  if let Some(owner) = kernel_stack::guard_page_owner(fault_address) {
+    kernel_stack::stack_overflow(fault_address, owner);
+  }
+
+  if fault_address < KERNEL_BASE && error_code & (PAGE_FAULT_PROTECTION | PAGE_FAULT_USER) == PAGE_FAULT_PROTECTION {
+    check_supervisor_protection(&exception_stack_frame, fault_address, error_code);
+  }
+
+  if fault_address < KERNEL_BASE || error_code & PAGE_FAULT_USER != 0 {
+    if let Some(process) = process::my_process() {
+      if handle_user_page_fault(process, fault_address, error_code) {
+        return;
+      }
+
+      if error_code & PAGE_FAULT_USER != 0 {
+        println!("pid {}: invalid access at {:#x} with error code {:#x}, killing process",
+                 process.id, fault_address, error_code);
+        process::exit(-1);
+      }
+    }
+  }
+
+  println!("Page fault at {:#x} with error code {:#x}!", fault_address, error_code);
   println!("Interrupt Stack Frame {:#?}", exception_stack_frame);
 
   loop{}
 
 }
 
+/// Panics with a diagnostic if a protection fault of the kernel on a user address was caused by SMEP or SMAP.
+/// Such a fault is a kernel bug, and resolving it like a user fault would only make the access fault again.
+fn check_supervisor_protection(exception_stack_frame: &InterruptStackFrame, fault_address: usize, error_code: u32) {
+  if paging::has_smep() && error_code & PAGE_FAULT_INSTRUCTION_FETCH != 0 {
+    panic!("SMEP violation: the kernel executed the user page at {:#x}", fault_address);
+  }
+  if paging::has_smap() && exception_stack_frame.cpu_flags & EFlags::FLAGS_AC.bits() == 0 {
+    panic!("SMAP violation: the kernel accessed user address {:#x} from eip {:#x} outside of with_user_access \
+            (error code {:#x})", fault_address, exception_stack_frame.instruction_pointer, error_code);
+  }
+}
+
+/// Tries to resolve a page fault on a user address of the current process.
+/// user_memory also calls this to bring in the pages a system call accesses, with the error code the access would
+/// have caused.
+/// Returns true if the faulting instruction can be restarted.
+pub fn handle_user_page_fault(process: &mut Process, fault_address: usize, error_code: u32) -> bool {
+  if fault_address >= KERNEL_BASE {
+    return false;
+  }
+
+  // Pages of mmap areas are filled in from the area's backing.
+  let write = error_code & PAGE_FAULT_WRITE != 0;
+  if let Some(resolved) = vma::handle_page_fault(process, fault_address, write, error_code & PAGE_FAULT_PROTECTION != 0) {
+    return resolved;
+  }
+
+  let lazy = process.is_lazy_address(fault_address);
+  let page_directory = match process.address_space.as_mut() {
+    Some(address_space) => address_space.page_directory(),
+    None => {
+      return false;
+    }
+  };
+  if error_code & PAGE_FAULT_PROTECTION != 0 {
+    // A write to a page that fork shared with another process.
+    write && virtual_memory::handle_copy_on_write(page_directory, fault_address)
+  } else if swap::swap_in(page_directory, fault_address) {
+    true
+  } else {
+    // Heap and stack pages are allocated on their first use.
+    lazy && virtual_memory::map_zeroed_page(page_directory, fault_address)
+  }
+}
+
 /// Load the Interrupt Descriptor Table.
 pub fn init() {
   IDT.load();
-}
\ No newline at end of file
+}
@@ -0,0 +1,135 @@
+//! # Kernel Heap
+//! The global allocator behind alloc::{Box, Vec, BTreeMap}.
+//! Small objects are allocated from size classes. A size class cuts whole pages from the page allocator into
+//! equally sized blocks and keeps its free blocks in a linked list. Since every block size is a power of two and
+//! pages are page aligned, each block is aligned to its size.
+//! Objects larger than the biggest size class are allocated directly from the page allocator.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::null_mut;
+use spin::Mutex;
+use mmu::PAGE_SIZE;
+use page_allocator::{FREE_PAGE_LIST, MAX_ORDER, order_for_size, PageOwner};
+
+/// The block sizes of the size classes.
+const SIZE_CLASSES: [usize; 9] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+#[global_allocator]
+static KERNEL_HEAP: KernelHeap = KernelHeap::new();
+
+struct FreeBlock {
+  next: Option<&'static mut FreeBlock>
+}
+
+struct SizeClass {
+  block_size: usize,
+  free_blocks: Option<&'static mut FreeBlock>
+}
+
+pub struct KernelHeap {
+  size_classes: Mutex<[SizeClass; SIZE_CLASSES.len()]>
+}
+
+impl SizeClass {
+
+  const fn new(block_size: usize) -> Self {
+    Self {
+      block_size,
+      free_blocks: None
+    }
+  }
+
+  /// Takes a block from the free list, cutting a new page into blocks if the list is empty.
+  fn alloc(&mut self) -> Option<usize> {
+    if self.free_blocks.is_none() {
+      let page = FREE_PAGE_LIST.alloc_page(PageOwner::KernelHeap)?;
+      let mut block = page;
+      while block + self.block_size <= page + PAGE_SIZE {
+        unsafe {
+          self.dealloc(block);
+        }
+        block += self.block_size;
+      }
+    }
+
+    let block = self.free_blocks.take()?;
+    self.free_blocks = block.next.take();
+    Some(block as *mut FreeBlock as usize)
+  }
+
+  /// Returns a block to the free list.
+  unsafe fn dealloc(&mut self, address: usize) {
+    let block_pointer = address as *mut FreeBlock;
+    block_pointer.write(FreeBlock {
+      next: self.free_blocks.take()
+    });
+    self.free_blocks = Some(&mut *block_pointer);
+  }
+
+}
+
+impl KernelHeap {
+
+  const fn new() -> Self {
+    Self {
+      size_classes: Mutex::new([
+        SizeClass::new(SIZE_CLASSES[0]),
+        SizeClass::new(SIZE_CLASSES[1]),
+        SizeClass::new(SIZE_CLASSES[2]),
+        SizeClass::new(SIZE_CLASSES[3]),
+        SizeClass::new(SIZE_CLASSES[4]),
+        SizeClass::new(SIZE_CLASSES[5]),
+        SizeClass::new(SIZE_CLASSES[6]),
+        SizeClass::new(SIZE_CLASSES[7]),
+        SizeClass::new(SIZE_CLASSES[8]),
+      ])
+    }
+  }
+
+}
+
+/// Returns the index of the smallest size class that satisfies layout, or None if the object needs whole pages.
+fn size_class_index(layout: &Layout) -> Option<usize> {
+  let size = layout.size().max(layout.align());
+  SIZE_CLASSES.iter().position(|&block_size| block_size >= size)
+}
+
+/// Returns the page allocator order used for an object that is too large for the size classes.
+fn large_order(layout: &Layout) -> usize {
+  order_for_size(layout.size().max(layout.align()))
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    let address = match size_class_index(&layout) {
+      Some(index) => {
+        self.size_classes.lock()[index].alloc()
+      }
+      None => {
+        let order = large_order(&layout);
+        if order > MAX_ORDER {
+          return null_mut();
+        }
+        FREE_PAGE_LIST.alloc_pages(order, PageOwner::KernelHeap)
+      }
+    };
+
+    match address {
+      Some(address) => address as *mut u8,
+      None => null_mut()
+    }
+  }
+
+  unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
+    match size_class_index(&layout) {
+      Some(index) => {
+        self.size_classes.lock()[index].dealloc(pointer as usize);
+      }
+      None => {
+        FREE_PAGE_LIST.dealloc_pages(pointer as usize, large_order(&layout));
+      }
+    }
+  }
+
+}
@@ -0,0 +1,176 @@
+//! # Kernel Stacks
+//! Each process gets KERNEL_STACK_SIZE bytes of kernel stack in the region at KERNEL_STACKS_BASE. The region is
+//! divided into slots. A slot starts with an unmapped guard page followed by the stack, so a stack that overflows
+//! runs into the guard page instead of into the stack below it.
+//! The region has page tables of its own that every page directory points at, so a stack mapped once is mapped in
+//! every address space.
+//! A freed stack is only invalidated in the TLB of the cpu that frees it. Like swap, this relies on each cpu loading
+//! cr3 before it runs a process, which is the only time it uses that process's stack: the stack pages are not global,
+//! so the load drops the entries another cpu kept of a slot's previous stack.
+//! An overflow faults while the CPU pushes onto the stack, and the CPU faults again when it tries to push the page
+//! fault's frame. The resulting double fault is handled by a separate task with a stack of its own, which reports
+//! the overflow.
+
+use spin::Mutex;
+use x86::controlregs;
+use acpi::MAX_CPUS;
+use memory_layout::{KERNEL_STACKS_BASE, KERNEL_STACKS_TOP, map_physical_virtual, map_virtual_to_physical};
+use mmu::{LARGE_PAGE_SIZE, page_directory_index, page_table_index, PAGE_SIZE, SEGMENT_KERNEL_CODE, SEGMENT_KERNEL_DATA, TaskState};
+use page_allocator::{FREE_PAGE_LIST, PageOwner};
+use paging::{self, PAddr, PD, PDEntry, PDFlags, PT, PTEntry, PTFlags};
+use param::KERNEL_STACK_SIZE;
+
+/// The size of a slot: the guard page and the stack.
+const SLOT_SIZE: usize = PAGE_SIZE + KERNEL_STACK_SIZE;
+const SLOT_COUNT: usize = (KERNEL_STACKS_TOP - KERNEL_STACKS_BASE) / SLOT_SIZE;
+
+/// The size of the stack of each cpu's double fault task.
+const DOUBLE_FAULT_STACK_SIZE: usize = PAGE_SIZE;
+
+/// The number of page tables that cover the region.
+const PAGE_TABLE_COUNT: usize = (KERNEL_STACKS_TOP - KERNEL_STACKS_BASE) / LARGE_PAGE_SIZE;
+
+/// The page tables of the kernel stack region.
+static mut STACK_PAGE_TABLES: [*mut PT; PAGE_TABLE_COUNT] = [0 as *mut PT; PAGE_TABLE_COUNT];
+
+/// The process id that owns each slot, or 0 for a free slot.
+static SLOTS: Mutex<[usize; SLOT_COUNT]> = Mutex::new([0; SLOT_COUNT]);
+
+static mut DOUBLE_FAULT_TASKS: [TaskState; MAX_CPUS] = [TaskState::new(); MAX_CPUS];
+static mut DOUBLE_FAULT_STACKS: [[u8; DOUBLE_FAULT_STACK_SIZE]; MAX_CPUS] = [[0; DOUBLE_FAULT_STACK_SIZE]; MAX_CPUS];
+
+/// Allocates the page tables of the kernel stack region.
+/// Must be called after page_allocator::init and before the first page directory is created.
+pub fn init() {
+  for index in 0..PAGE_TABLE_COUNT {
+    let page_table = FREE_PAGE_LIST.alloc_page(PageOwner::PageTable).expect("kernel_stack::init: out of memory");
+    unsafe {
+      (page_table as *mut u8).write_bytes(0, PAGE_SIZE);
+      STACK_PAGE_TABLES[index] = page_table as *mut PT;
+    }
+  }
+}
+
+/// Points the entries of page_directory for the kernel stack region at the region's page tables.
+pub fn install(page_directory: &mut PD) {
+  for (index, &page_table) in unsafe { STACK_PAGE_TABLES.iter() }.enumerate() {
+    if !page_table.is_null() {
+      page_directory[page_directory_index(KERNEL_STACKS_BASE) + index] =
+        PDEntry::new(PAddr::from(map_virtual_to_physical(page_table as usize)), PDFlags::P | PDFlags::RW);
+    }
+  }
+}
+
+/// Returns true if the page directory entry at index points at one of the region's page tables, which belong to
+/// every page directory.
+pub fn is_stack_page_directory_index(index: usize) -> bool {
+  let first = page_directory_index(KERNEL_STACKS_BASE);
+  first <= index && index < first + PAGE_TABLE_COUNT
+}
+
+/// The lowest address of the stack in a slot. The guard page is just below it.
+fn stack_bottom(slot: usize) -> usize {
+  KERNEL_STACKS_BASE + slot * SLOT_SIZE + PAGE_SIZE
+}
+
+/// The page table entry of an address in the region.
+fn page_table_entry(address: usize) -> &'static mut PTEntry {
+  let page_table = unsafe { &mut *STACK_PAGE_TABLES[(address - KERNEL_STACKS_BASE) / LARGE_PAGE_SIZE] };
+  &mut page_table[page_table_index(address)]
+}
+
+/// Allocates and maps a kernel stack for a process.
+/// Returns the lowest address of the stack, which grows down from that address plus KERNEL_STACK_SIZE.
+/// Returns None if every slot is in use or there is not enough memory.
+pub fn alloc(process_id: usize) -> Option<usize> {
+  let mut slots = SLOTS.lock();
+  let slot = slots.iter().position(|&owner| owner == 0)?;
+
+  let bottom = stack_bottom(slot);
+  let mut address = bottom;
+  while address < bottom + KERNEL_STACK_SIZE {
+    let page = match FREE_PAGE_LIST.alloc_page(PageOwner::KernelStack) {
+      Some(page) => page,
+      None => {
+        unmap(bottom, address);
+        return None;
+      }
+    };
+    *page_table_entry(address) = PTEntry::new(PAddr::from(map_virtual_to_physical(page)), PTFlags::P | PTFlags::RW | paging::no_execute());
+    address += PAGE_SIZE;
+  }
+
+  slots[slot] = process_id;
+  Some(bottom)
+}
+
+/// Unmaps and frees a kernel stack returned by alloc.
+pub fn dealloc(bottom: usize) {
+  let slot = (bottom - KERNEL_STACKS_BASE) / SLOT_SIZE;
+  unmap(bottom, bottom + KERNEL_STACK_SIZE);
+  SLOTS.lock()[slot] = 0;
+}
+
+/// Unmaps and frees the stack pages in [start, end).
+/// Other cpus may still have the pages in their TLBs until they next load cr3, which they do before they run the
+/// process the slot is given to next.
+fn unmap(start: usize, end: usize) {
+  let mut address = start;
+  while address < end {
+    let page_table_entry = page_table_entry(address);
+    if page_table_entry.is_present() {
+      unsafe {
+        FREE_PAGE_LIST.dealloc_page(map_physical_virtual(page_table_entry.address().as_usize()));
+        *page_table_entry = PTEntry(0);
+        paging::invalidate_page(address);
+      }
+    }
+    address += PAGE_SIZE;
+  }
+}
+
+/// Returns Some if address is in a guard page, with the id of the process whose stack is above it.
+/// The id is None if the slot table is locked, which happens when the fault interrupted alloc or dealloc.
+pub fn guard_page_owner(address: usize) -> Option<Option<usize>> {
+  if address < KERNEL_STACKS_BASE || address >= KERNEL_STACKS_BASE + SLOT_COUNT * SLOT_SIZE {
+    return None;
+  }
+
+  let slot = (address - KERNEL_STACKS_BASE) / SLOT_SIZE;
+  if address >= stack_bottom(slot) {
+    return None;
+  }
+  Some(SLOTS.try_lock().map(|slots| slots[slot]))
+}
+
+/// Panics with the owner of the guard page at fault_address.
+pub fn stack_overflow(fault_address: usize, owner: Option<usize>) -> ! {
+  match owner {
+    Some(process_id) => panic!("kernel stack overflow in pid {} at {:#x}", process_id, fault_address),
+    None => panic!("kernel stack overflow at {:#x}", fault_address)
+  }
+}
+
+/// Returns the task state of a cpu's double fault task, set up to run double_fault_task.
+/// # Arguments
+/// * 'cpu' - The index of the cpu in CPUS.
+/// * 'page_directory' - The physical address of a page directory with the kernel mappings.
+/// # Safety
+/// The task state is rewritten in place, so this must only be called while the cpu is set up, before its double
+/// fault task can run.
+pub unsafe fn double_fault_task_state(cpu: usize, page_directory: usize) -> &'static TaskState {
+  let stack_top = DOUBLE_FAULT_STACKS[cpu].as_ptr() as usize + DOUBLE_FAULT_STACK_SIZE;
+  DOUBLE_FAULT_TASKS[cpu].set_task(double_fault_task as usize as u32, stack_top as u32,
+                                   page_directory, (SEGMENT_KERNEL_CODE << 3) as u16, (SEGMENT_KERNEL_DATA << 3) as u16);
+  &DOUBLE_FAULT_TASKS[cpu]
+}
+
+/// Runs as its own task after a double fault. The error code the CPU pushed, which is always 0, is where a return
+/// address would be, and the function never returns.
+extern "C" fn double_fault_task() -> ! {
+  let fault_address = unsafe { controlregs::cr2() };
+  if let Some(owner) = guard_page_owner(fault_address) {
+    stack_overflow(fault_address, owner);
+  }
+  panic!("double fault, last page fault at {:#x}", fault_address);
+}
@@ -3,7 +3,9 @@
 #![feature(abi_x86_interrupt)]
 #![feature(format_args_nl)]
 #![feature(const_mut_refs)]
+#![feature(alloc_error_handler)]
 
+extern crate alloc;
 #[macro_use]
 extern crate bitfield;
 #[macro_use]
@@ -12,8 +14,11 @@ extern crate spin;
 extern crate x86;
 
 pub mod arch;
+pub mod boot_options;
 #[macro_use]
 pub mod console;
+pub mod elf;
+pub mod exec;
 pub mod file;
 pub mod fs;
 pub mod ioapic;
@@ -21,29 +26,41 @@ pub mod interrupt_controller;
 pub mod kbd;
 pub mod string;
 pub mod syscall;
+pub mod sysfile;
 pub mod sysproc;
 pub mod mmu;
+pub mod paging;
 pub mod param;
 pub mod pipe;
 pub mod process;
+pub mod scheduling;
 pub mod trap;
 pub mod traps;
 pub mod types;
 
 pub mod page_allocator;
+pub mod kernel_heap;
+pub mod kernel_stack;
+pub mod slab;
+pub mod shm;
+pub mod swap;
+pub mod vma;
+pub mod address_space;
+pub mod user_memory;
 pub mod interrupts;
 mod memory_layout;
+mod memory_map;
 mod virtual_memory;
 mod ide;
 mod local_interrupt_controller;
 mod acpi;
 
+use core::alloc::Layout;
 use core::arch::asm;
 use core::panic::PanicInfo;
 use x86::bits32::paging::{PAGE_SIZE_ENTRIES, PD, PDEntry};
 use acpi::ACPI2;
 use memory_layout::KERNEL_BASE;
-use mmu::PAGE_DIRECTORY_INDEX_SHIFT;
 use process::{get_current_cpu, user_init};
 use virtual_memory::kmalloc;
 
@@ -59,10 +76,14 @@ pub extern "C" fn rust_main() {
 
     ACPI2.lock().populate_cpu_info();
 
+    boot_options::init();
+    memory_map::print();
     page_allocator::init();
+    kernel_stack::init();
 
     unsafe {
         kmalloc();
+        page_allocator::init_high_memory();
         //multi_processor::init();
         //ACPI2.lock().populate_cpu_info();
         //println!("MP configured.");
@@ -76,11 +97,17 @@ pub extern "C" fn rust_main() {
         interrupt_controller::init();
     }
 
-    println!("Current CPU: {}", get_current_cpu().apicid);
+    console::init();
 
-    //user_init();
+    ide::init();
+    fs::init();
 
-    loop {}
+    println!("Current CPU: {}", get_current_cpu().apicid);
+
+    user_init();
+    #[cfg(feature = "share_test")]
+    scheduling::share_test::start();
+    process::scheduler();
 }
 
 #[panic_handler]
@@ -89,6 +116,12 @@ fn panic(info: &PanicInfo) -> ! {
     loop {}
 }
 
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    println!("Kernel heap out of memory: {} bytes aligned to {}", layout.size(), layout.align());
+    loop {}
+}
+
 #[repr(align(4096))]
 pub struct PD1([PDEntry; PAGE_SIZE_ENTRIES]);
 
@@ -97,6 +130,8 @@ const fn default_page_directory() -> PD1 {
     const PDE_RW   : u32 = 0x002;   // Readable/Writeable
     const PDE_PS  : u32 = 0x080;   // Page Size
     const ADDRESS_MASK_PSE: u32 = !0x3fffff;
+    // The boot page directory always uses 32-bit paging, even with the pae feature.
+    const PAGE_DIRECTORY_INDEX_SHIFT: usize = 22;
 
     let mut default_page_directory: PD1 = PD1([PDEntry(0); PAGE_SIZE_ENTRIES]);
 
@@ -98,6 +98,15 @@ unsafe fn read(register: u32) -> u32 {
 }
 
 
+/// Acknowledges the interrupt being handled, so that the local interrupt controller delivers the next one.
+pub fn end_of_interrupt() {
+  unsafe {
+    if !LOCAL_INTERRUPT_CONTROLLER.is_null() {
+      write(END_OF_INTERRUPT, 0);
+    }
+  }
+}
+
 pub fn get_id() -> u8 {
   unsafe {
     if LOCAL_INTERRUPT_CONTROLLER.is_null() {
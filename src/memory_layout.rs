@@ -1,5 +1,12 @@
+use mmu::PAGE_SIZE;
+use param::MAX_USER_STACK_SIZE;
+
 /// # Memory layout
 
+/// Where bootasm.S stores the BIOS memory map.
+/// Change in bootasm.S as well.
+pub const E820_MAP: usize = 0x500;
+
 /// Start of extended memory
 pub const EXTENDED_MEMORY: usize = 0x100000;
 
@@ -8,12 +15,27 @@ pub const PHYSICAL_TOP: usize = 0xE000000;
 /// Other devices are at high addresses
 pub const DEVICE_SPACE: usize = 0xFE000000;
 
+/// Process kernel stacks are mapped in this region, which one page table covers. Every page directory shares it.
+pub const KERNEL_STACKS_BASE: usize = 0xFD000000;
+pub const KERNEL_STACKS_TOP: usize = KERNEL_STACKS_BASE + 0x400000;
+
 /// Key addresses for address space layout (see kmap in vm.c for layout)
 /// First kernel virtual address
 pub const KERNEL_BASE: usize = 0x80000000;
 /// Address where kernel is linked
 pub const KERNEL_LINK: usize = KERNEL_BASE + EXTENDED_MEMORY;
 
+/// The top of the user stack. The stack grows down from here as it is used.
+pub const USER_STACK_TOP: usize = KERNEL_BASE;
+/// The lowest address the user stack can grow to.
+pub const USER_STACK_LIMIT: usize = USER_STACK_TOP - MAX_USER_STACK_SIZE;
+/// The top of the region where mmap places areas. One unmapped page separates it from the stack.
+pub const USER_MMAP_TOP: usize = USER_STACK_LIMIT - PAGE_SIZE;
+/// The bottom of the region where mmap places areas.
+pub const USER_MMAP_BASE: usize = 0x40000000;
+/// The highest address the user heap can grow to. One unmapped page separates it from the mmap region.
+pub const USER_HEAP_LIMIT: usize = USER_MMAP_BASE - PAGE_SIZE;
+
 /// Maps a virtual address to a physical address.
 pub const fn map_virtual_to_physical(address: usize) -> usize {
   address.overflowing_sub(KERNEL_BASE).0
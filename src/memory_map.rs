@@ -0,0 +1,110 @@
+//! # Memory Map
+//! The physical memory map reported by the BIOS.
+//! bootasm.S collects the map with int 0x15, eax 0xe820 before switching to protected mode.
+//! It stores the number of entries at E820_MAP and the entries right after it.
+
+use core::slice;
+use memory_layout::{E820_MAP, map_physical_virtual};
+
+/// The maximum number of entries bootasm.S collects.
+const E820_MAX: usize = 32;
+
+/// A range of physical memory reported by the BIOS.
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct MemoryMapEntry {
+  base: u64,
+  length: u64,
+  region_type: u32,
+  extended_attributes: u32
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RegionType {
+  Usable,
+  Reserved,
+  AcpiReclaimable,
+  AcpiNvs,
+  Bad,
+  Unknown(u32)
+}
+
+impl MemoryMapEntry {
+
+  /// The first physical address of the region.
+  pub fn start(&self) -> u64 {
+    self.base
+  }
+
+  /// The physical address just past the end of the region.
+  pub fn end(&self) -> u64 {
+    self.base.saturating_add(self.length)
+  }
+
+  pub fn region_type(&self) -> RegionType {
+    match self.region_type {
+      1 => RegionType::Usable,
+      2 => RegionType::Reserved,
+      3 => RegionType::AcpiReclaimable,
+      4 => RegionType::AcpiNvs,
+      5 => RegionType::Bad,
+      region_type => RegionType::Unknown(region_type)
+    }
+  }
+
+}
+
+/// Returns the entries of the memory map.
+/// The map is empty if the BIOS does not support e820, or if the kernel was not started by bootasm.S.
+pub fn entries() -> &'static [MemoryMapEntry] {
+  unsafe {
+    let count = *(map_physical_virtual(E820_MAP) as *const u32) as usize;
+    let entries = map_physical_virtual(E820_MAP + 4) as *const MemoryMapEntry;
+    slice::from_raw_parts(entries, count.min(E820_MAX))
+  }
+}
+
+/// Calls f with each part of the physical range [start, end) that is usable RAM.
+/// Parts of a usable region that are also claimed by another region type are skipped.
+pub fn for_each_usable_range<F: FnMut(usize, usize)>(start: usize, end: usize, mut f: F) {
+  for entry in entries() {
+    if entry.region_type() != RegionType::Usable {
+      continue;
+    }
+
+    let range_start = entry.start().max(start as u64);
+    let range_end = entry.end().min(end as u64);
+    if range_start < range_end {
+      without_reserved(range_start as usize, range_end as usize, &mut f);
+    }
+  }
+}
+
+/// Calls f with the parts of [start, end) that do not overlap any region that is not usable.
+fn without_reserved(start: usize, end: usize, f: &mut dyn FnMut(usize, usize)) {
+  for entry in entries() {
+    if entry.region_type() == RegionType::Usable {
+      continue;
+    }
+
+    if entry.start() < end as u64 && (start as u64) < entry.end() {
+      if (start as u64) < entry.start() {
+        without_reserved(start, entry.start() as usize, f);
+      }
+      if entry.end() < end as u64 {
+        without_reserved(entry.end() as usize, end, f);
+      }
+      return;
+    }
+  }
+
+  f(start, end);
+}
+
+/// Prints the memory map.
+pub fn print() {
+  println!("Memory map:");
+  for entry in entries() {
+    println!("  [{:#010x}, {:#010x}) {:?}", entry.start(), entry.end(), entry.region_type());
+  }
+}
@@ -1,4 +1,5 @@
 use core::ffi;
+use paging::EntryBits;
 
 #[repr(C)]
 #[derive(Copy, Clone)]
@@ -84,6 +85,32 @@ impl TaskState {
             iomb: 0
         }
     }
+
+    /// Sets the stack the CPU switches to when an interrupt arrives in user mode.
+    /// Also disables the I/O permission bitmap, so user mode can not use I/O ports.
+    pub fn set_kernel_stack(&mut self, stack_segment: u16, stack_pointer: u32) {
+        self.ss0 = stack_segment;
+        self.esp0 = stack_pointer;
+        self.iomb = 0xFFFF;
+    }
+
+    /// Sets up the state a task gate's task starts with: entry runs on stack_pointer with interrupts disabled.
+    /// # Arguments
+    /// * 'page_directory' - The physical address of the page directory loaded into cr3.
+    pub fn set_task(&mut self, entry: u32, stack_pointer: u32, page_directory: usize, code_segment: u16, data_segment: u16) {
+        self.eip = entry as *const u32;
+        self.esp = stack_pointer as *const u32;
+        self.cr3 = page_directory as *const ffi::c_void;
+        // Only the reserved bit is set.
+        self.eflags = 0x2;
+        self.cs = code_segment;
+        self.ss = data_segment;
+        self.ds = data_segment;
+        self.es = data_segment;
+        self.fs = data_segment;
+        self.gs = data_segment;
+        self.iomb = 0xFFFF;
+    }
 }
 
 bitfield!{
@@ -107,6 +134,12 @@ bitfield!{
 }
 
 pub const PAGE_SIZE: usize = 4096;
+/// The size of a page mapped directly by a page directory entry: 4MiB with PSE, 2MiB with PAE.
+pub const LARGE_PAGE_SIZE: usize = 1 << PAGE_DIRECTORY_INDEX_SHIFT;
+/// The number of entries in a page directory. With PAE, in all four page directories together.
+pub const PAGE_DIRECTORY_ENTRIES: usize = 1 << (32 - PAGE_DIRECTORY_INDEX_SHIFT);
+/// The number of entries in a page table.
+pub const PAGE_TABLE_ENTRIES: usize = 1 << (PAGE_DIRECTORY_INDEX_SHIFT - PAGE_TABLE_INDEX_SHIFT);
 
 /// Rounds up to the nearest page.
 pub const fn page_round_up(address: usize) -> usize {
@@ -120,15 +153,25 @@ pub const fn page_round_down(address: usize) -> usize {
 
 /// Get the index of a virtual address's page directory entry in a page directory.
 pub const fn page_directory_index(virtual_address: usize) -> usize {
-    (virtual_address >> PAGE_DIRECTORY_INDEX_SHIFT) & 0x3FFusize
+    (virtual_address >> PAGE_DIRECTORY_INDEX_SHIFT) & (PAGE_DIRECTORY_ENTRIES - 1)
 }
 
 /// Get the index of a virtual address's page table entry in a page table.
 pub const fn page_table_index(virtual_address: usize) -> usize {
-    (virtual_address >> PAGE_TABLE_INDEX_SHIFT) & 0x3FFusize
+    (virtual_address >> PAGE_TABLE_INDEX_SHIFT) & (PAGE_TABLE_ENTRIES - 1)
 }
 
+/// A page table entry bit left for the operating system.
+/// Marks a read-only page that is shared after fork and gets copied on the first write.
+pub const PTE_COPY_ON_WRITE: EntryBits = 0x200;
+/// A page table entry bit left for the operating system.
+/// Marks a page that is not present because it was written to swap. The address bits hold the swap slot.
+pub const PTE_SWAPPED: EntryBits = 0x400;
+
+#[cfg(not(feature = "pae"))]
 pub const PAGE_DIRECTORY_INDEX_SHIFT: usize = 22; // offset of PDX in a linear address
+#[cfg(feature = "pae")]
+pub const PAGE_DIRECTORY_INDEX_SHIFT: usize = 21; // offset of PDX in a linear address, over all four page directories
 pub const PAGE_TABLE_INDEX_SHIFT: usize = 12; // offset of PDX in a linear address
 
 // various segment selectors
@@ -142,6 +185,8 @@ pub const SEGMENT_USER_CODE: usize = 3;
 pub const SEGMENT_USER_DATA: usize = 4;
 // this process's task state
 pub const SEGMENT_PROCESS_TASK_STATE: usize = 5;
+// the task that handles double faults
+pub const SEGMENT_DOUBLE_FAULT_TASK_STATE: usize = 6;
 
 // cpu->gdt[SEGMENT_COUNT] holds the above segments.
-pub const SEGMENT_COUNT: usize = 6;
\ No newline at end of file
+pub const SEGMENT_COUNT: usize = 7;
\ No newline at end of file
@@ -1,31 +1,132 @@
 //! # Page Allocator
 //! A physical page allocator.
 //! The free memory is allocated from the end of the kernel to the end of physical memory.
-//! A linked list of free pages is used to keep track of allocations.
+//! Free memory is managed by a buddy allocator. A block of order n is 2^n physically contiguous pages and is
+//! aligned to its own size. Each order keeps a linked list of its free blocks. When a block is freed it is merged
+//! with its buddy, the other half of the next larger block, as long as the buddy is also free.
+//! Every allocation names a PageOwner. With the page_allocator_debug feature, the allocator records the owner and
+//! call site of each allocated block, panics on double frees, fills freed blocks with POISON and can dump the
+//! outstanding allocations grouped by owner.
+//! The free lists are a global pool behind a lock. Single pages, which are most allocations, go through a small
+//! cache of free pages for each cpu instead. A cache that runs empty is refilled from the pool, and a cache that runs
+//! full gives pages back to it, CACHE_BATCH pages at a time, so the pool lock is taken once per batch. Reference
+//! counts are atomic, so sharing pages, as fork does for every user page, takes no lock at all.
 
-use core::ops::Deref;
+use core::sync::atomic::{AtomicU16, Ordering};
+#[cfg(feature = "page_allocator_debug")]
+use core::panic::Location;
+use spin::{Mutex, MutexGuard};
+use acpi::MAX_CPUS;
 use console::print;
 use memory_layout;
+use memory_map;
+use memory_layout::{map_virtual_to_physical, PHYSICAL_TOP};
 use mmu::{page_round_up, PAGE_SIZE};
+use process::{get_current_cpu_id, interrupts_enabled};
+use swap;
 
 /// The default allocator used by the kernel.
-pub static mut FREE_PAGE_LIST: AllocationList = AllocationList::new();
+pub static FREE_PAGE_LIST: PageAllocator = PageAllocator::new();
+
+/// The largest block order. A block of this order is 1024 pages, the size of a 4MB PSE page.
+pub const MAX_ORDER: usize = 10;
+
+/// The number of physical pages the allocator can keep track of.
+const PAGE_COUNT: usize = PHYSICAL_TOP / PAGE_SIZE;
+
+/// The number of free pages a cpu's cache can hold.
+const CACHE_SIZE: usize = 32;
+/// The number of pages moved between a cache and the pool at once.
+const CACHE_BATCH: usize = CACHE_SIZE / 2;
+
+/// The byte freed blocks are filled with in debug mode, so that a use after free reads an obvious pattern.
+#[cfg(feature = "page_allocator_debug")]
+const POISON: u8 = 0x6b;
+
+/// The number of distinct call sites dump prints for each owner.
+#[cfg(feature = "page_allocator_debug")]
+const MAX_CALL_SITES: usize = 8;
+
+/// What a block of pages is allocated for.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PageOwner {
+  PageDirectory,
+  PageTable,
+  UserPage,
+  KernelStack,
+  KernelHeap,
+  Slab,
+  SharedMemory
+}
+
+#[cfg(feature = "page_allocator_debug")]
+const OWNERS: [PageOwner; 7] = [
+  PageOwner::PageDirectory,
+  PageOwner::PageTable,
+  PageOwner::UserPage,
+  PageOwner::KernelStack,
+  PageOwner::KernelHeap,
+  PageOwner::Slab,
+  PageOwner::SharedMemory
+];
+
+/// The record debug mode keeps for the first page of each block.
+#[cfg(feature = "page_allocator_debug")]
+#[derive(Copy, Clone)]
+struct Allocation {
+  /// None if the block is free.
+  owner: Option<PageOwner>,
+  order: u8,
+  call_site: Option<&'static Location<'static>>
+}
+
+#[cfg(feature = "page_allocator_debug")]
+impl Allocation {
+  const FREE: Allocation = Allocation { owner: None, order: 0, call_site: None };
+}
 
 #[repr(C)]
 struct AllocationNode {
   next: Option<&'static mut AllocationNode>
 }
 
-/// Stores a list of free pages.
-/// Removing a page allocates it.
-/// Adding a page deallocates it.
-/// Pages are stored using their virtual addresses.
-/// The AllocationNode is stored at the begging of the free page.
+/// Stores a list of free blocks for each order.
+/// Removing a block allocates it.
+/// Adding a block deallocates it.
+/// Blocks are stored using their virtual addresses.
+/// The AllocationNode is stored at the begging of the free block.
 #[repr(C)]
-pub struct AllocationList {
-  head: AllocationNode
+struct AllocationList {
+  free_lists: [AllocationNode; MAX_ORDER + 1],
+  /// The order + 1 of the free block that starts at each physical page, or 0 if no free block starts there.
+  free_orders: [u8; PAGE_COUNT],
+  /// The number of pages given to the allocator.
+  managed_pages: usize,
+  /// The number of pages in the free lists.
+  free_pages: usize,
+  #[cfg(feature = "page_allocator_debug")]
+  allocations: [Allocation; PAGE_COUNT]
+}
+
+/// Free single pages kept for one cpu.
+struct PageCache {
+  pages: [usize; CACHE_SIZE],
+  count: usize
+}
+
+/// The global pool and the caches of each cpu.
+pub struct PageAllocator {
+  pool: Mutex<AllocationList>,
+  caches: [Mutex<PageCache>; MAX_CPUS]
 }
 
+/// The number of references to each allocated block, stored at the block's first page.
+/// Pages shared between page directories, like copy-on-write pages after fork, have more than one reference.
+static REFERENCE_COUNTS: [AtomicU16; PAGE_COUNT] = {
+  const NO_REFERENCES: AtomicU16 = AtomicU16::new(0);
+  [NO_REFERENCES; PAGE_COUNT]
+};
+
 impl AllocationNode {
 
   const fn new() -> Self {
@@ -43,24 +144,80 @@ extern "C" {
   static END_SYMBOL: usize;
 }
 
-/// Initialize the allocator using [page_round_up(end), 4MB].
-/// This is a maximum of 1024 4096 byte pages. As the code size grows, end also grows leaving less free pages.
+/// The end of the memory mapped by the entry page directory. kernel.ld checks that the kernel ends below it.
+pub const ENTRY_MEMORY_TOP: usize = 0x400000;
+
+/// Initialize the allocator using the usable memory in [page_round_up(end), 4MB].
+/// Only the first 4MB are mapped before kmalloc runs, so the rest of memory is added by init_high_memory.
 pub fn init() {
-  unsafe {
-    FREE_PAGE_LIST.dealloc_range(&END_SYMBOL as *const usize as usize, memory_layout::map_physical_virtual(0x400000));
+  let kernel_end = memory_layout::map_virtual_to_physical(unsafe { &END_SYMBOL as *const usize as usize });
+  // With page_allocator_debug the allocation records alone take up hundreds of KB of bss.
+  assert!(kernel_end < ENTRY_MEMORY_TOP, "page_allocator::init: the kernel ends at {:#x}, past the entry mapping", kernel_end);
+  free_usable_memory(kernel_end, ENTRY_MEMORY_TOP);
+}
+
+/// Adds the usable memory in [4MB, PHYSICAL_TOP] to the allocator.
+/// Must be called after kmalloc has mapped all of physical memory.
+pub fn init_high_memory() {
+  free_usable_memory(ENTRY_MEMORY_TOP, PHYSICAL_TOP);
+}
+
+/// Frees the parts of the physical range [start, end] that the memory map reports as usable RAM.
+/// Without a memory map only the memory below 4MB is trusted.
+fn free_usable_memory(start: usize, end: usize) {
+  if memory_map::entries().is_empty() {
+    if end <= ENTRY_MEMORY_TOP {
+      unsafe {
+        FREE_PAGE_LIST.dealloc_range(memory_layout::map_physical_virtual(start), memory_layout::map_physical_virtual(end));
+      }
+    } else {
+      println!("No memory map. Only using the first 4MB of memory.");
+    }
+    return;
   }
+
+  memory_map::for_each_usable_range(start, end, |range_start, range_end| {
+    unsafe {
+      FREE_PAGE_LIST.dealloc_range(memory_layout::map_physical_virtual(range_start), memory_layout::map_physical_virtual(range_end));
+    }
+  });
+}
+
+/// Returns the smallest order whose blocks can hold size bytes.
+pub const fn order_for_size(size: usize) -> usize {
+  let mut order = 0;
+  while (PAGE_SIZE << order) < size {
+    order += 1;
+  }
+  order
+}
+
+/// Returns the physical page number of a page's virtual address.
+fn page_number(address: usize) -> usize {
+  map_virtual_to_physical(address) / PAGE_SIZE
+}
+
+/// Returns the virtual address of a physical page number.
+fn page_address(page_number: usize) -> usize {
+  memory_layout::map_physical_virtual(page_number * PAGE_SIZE)
 }
 
 impl AllocationList {
 
   const fn new() -> Self {
+    const EMPTY_LIST: AllocationNode = AllocationNode::new();
     Self {
-      head: AllocationNode::new()
+      free_lists: [EMPTY_LIST; MAX_ORDER + 1],
+      free_orders: [0; PAGE_COUNT],
+      managed_pages: 0,
+      free_pages: 0,
+      #[cfg(feature = "page_allocator_debug")]
+      allocations: [Allocation::FREE; PAGE_COUNT]
     }
   }
 
   /// Frees pages in the range of [page_round_up(start),end].
-  /// Returns an option containing the address of the allocated page.
+  /// The range is freed using the largest aligned blocks that fit inside of it.
   /// # Arguments
   /// * 'start' - The start address of the page range. If this is not aligned to a 4096 byte address, then the address will be rounded up to the next page.
   /// * 'end' - The end address of the page range. This value does not need to be aligned.
@@ -69,49 +226,410 @@ impl AllocationList {
     let start_page = page_round_up(start);
 
     let mut page = start_page;
-    while page + PAGE_SIZE <= end {
-      self.dealloc_page(page);
-      page += PAGE_SIZE;
+    while page + PAGE_SIZE <= end && page_number(page) < PAGE_COUNT {
+      let mut order = 0;
+      while order < MAX_ORDER
+        && page_number(page) % (1 << (order + 1)) == 0
+        && page + (PAGE_SIZE << (order + 1)) <= end
+        && page_number(page) + (1 << (order + 1)) <= PAGE_COUNT {
+        order += 1;
+      }
+
+      self.managed_pages += 1 << order;
+      self.free_block(page, order);
+      page += PAGE_SIZE << order;
+    }
+  }
+
+  /// Removes a free block of 2^order pages from the free lists, splitting a larger block if needed.
+  fn alloc_pages(&mut self, order: usize) -> Option<usize> {
+    // Find the smallest free block that is large enough.
+    let mut block_order = order;
+    while self.free_lists[block_order].next.is_none() {
+      if block_order == MAX_ORDER {
+        return None;
+      }
+      block_order += 1;
+    }
+
+    let block = self.pop(block_order).unwrap();
+
+    // Split the block until it is the requested size, freeing the upper half each time.
+    while block_order > order {
+      block_order -= 1;
+      unsafe {
+        self.push(block + (PAGE_SIZE << block_order), block_order);
+      }
+    }
+
+    self.free_pages -= 1 << order;
+    Some(block)
+  }
+
+  /// Adds a block to the free lists and merges it with its free buddies.
+  unsafe fn free_block(&mut self, address: usize, order: usize) {
+    let mut page = page_number(address);
+    self.free_pages += 1 << order;
+
+    let mut order = order;
+    while order < MAX_ORDER {
+      let buddy = page ^ (1 << order);
+      if buddy >= PAGE_COUNT || self.free_orders[buddy] != order as u8 + 1 {
+        break;
+      }
+
+      self.remove(page_address(buddy), order);
+      page &= !(1 << order);
+      order += 1;
+    }
+
+    self.push(page_address(page), order);
+  }
+
+  /// Checks that a block being freed is allocated with the same order, then forgets its owner and poisons it.
+  #[cfg(feature = "page_allocator_debug")]
+  #[track_caller]
+  unsafe fn check_free(&mut self, address: usize, order: usize) {
+    let page = page_number(address);
+    let allocation = self.allocations[page];
+    match allocation.owner {
+      None => {
+        panic!("dealloc_pages: double free of {:#x} at {}", address, Location::caller());
+      }
+      Some(owner) => {
+        if allocation.order as usize != order {
+          panic!("dealloc_pages: {:#x} was allocated with order {} for {:?} at {} but freed with order {} at {}",
+                 address, allocation.order, owner, allocation.call_site.unwrap(), order, Location::caller());
+        }
+        let references = REFERENCE_COUNTS[page].load(Ordering::Relaxed);
+        if references > 1 {
+          panic!("dealloc_pages: {:#x} still has {} references at {}", address, references, Location::caller());
+        }
+      }
+    }
+
+    self.allocations[page] = Allocation::FREE;
+    (address as *mut u8).write_bytes(POISON, PAGE_SIZE << order);
+  }
+
+  /// Prints the outstanding allocations of each owner, grouped by call site.
+  #[cfg(feature = "page_allocator_debug")]
+  fn dump_allocations(&self) {
+    for &owner in OWNERS.iter() {
+      let mut call_sites: [(Option<&'static Location<'static>>, usize); MAX_CALL_SITES] = [(None, 0); MAX_CALL_SITES];
+      let mut blocks = 0;
+      let mut pages = 0;
+      let mut other_pages = 0;
+
+      for allocation in self.allocations.iter().filter(|allocation| allocation.owner == Some(owner)) {
+        let block_pages = 1 << allocation.order;
+        blocks += 1;
+        pages += block_pages;
+
+        match call_sites.iter().position(|&(call_site, _)| call_site.is_none() || call_site == allocation.call_site) {
+          Some(index) => {
+            call_sites[index].0 = allocation.call_site;
+            call_sites[index].1 += block_pages;
+          }
+          None => {
+            other_pages += block_pages;
+          }
+        }
+      }
+
+      if blocks == 0 {
+        continue;
+      }
+      println!("  {:?}: {} blocks, {} pages", owner, blocks, pages);
+      for &(call_site, call_site_pages) in call_sites.iter() {
+        if let Some(call_site) = call_site {
+          println!("    {}: {} pages", call_site, call_site_pages);
+        }
+      }
+      if other_pages > 0 {
+        println!("    other call sites: {} pages", other_pages);
+      }
+    }
+  }
+
+  /// Removes the first block from the free list of an order.
+  fn pop(&mut self, order: usize) -> Option<usize> {
+    let block = self.free_lists[order].next.take()?;
+    self.free_lists[order].next = block.next.take();
+
+    let address = block.address();
+    self.free_orders[page_number(address)] = 0;
+    Some(address)
+  }
+
+  /// Adds a block to the front of the free list of an order.
+  unsafe fn push(&mut self, address: usize, order: usize) {
+    // Create the new node. It will point to the current head's next.
+    let block = AllocationNode {
+      next: self.free_lists[order].next.take()
+    };
+
+    // Write the new node into the start of the block.
+    let block_pointer = address as *mut AllocationNode;
+    block_pointer.write(block);
+
+    // Set the current head to the new node.
+    self.free_lists[order].next = Some(&mut *block_pointer);
+    self.free_orders[page_number(address)] = order as u8 + 1;
+  }
+
+  /// Removes a specific block from the free list of an order.
+  fn remove(&mut self, address: usize, order: usize) {
+    let mut node = &mut self.free_lists[order];
+    loop {
+      match node.next.take() {
+        None => {
+          panic!("remove: block is not free");
+        }
+        Some(block) => {
+          if block.address() == address {
+            node.next = block.next.take();
+            break;
+          }
+          node.next = Some(block);
+          node = &mut **node.next.as_mut().unwrap();
+        }
+      }
+    }
+
+    self.free_orders[page_number(address)] = 0;
+  }
+
+}
+
+impl PageCache {
+
+  const fn new() -> Self {
+    Self {
+      pages: [0; CACHE_SIZE],
+      count: 0
+    }
+  }
+
+  fn pop(&mut self) -> Option<usize> {
+    if self.count == 0 {
+      return None;
+    }
+    self.count -= 1;
+    Some(self.pages[self.count])
+  }
+
+  fn push(&mut self, page: usize) {
+    self.pages[self.count] = page;
+    self.count += 1;
+  }
+
+  /// Moves up to count pages from the pool into the cache.
+  fn refill(&mut self, pool: &mut AllocationList, count: usize) {
+    for _ in 0..count {
+      match pool.alloc_pages(0) {
+        Some(page) => self.push(page),
+        None => break
+      }
+    }
+  }
+
+  /// Moves up to count pages from the cache back into the pool.
+  fn drain(&mut self, pool: &mut AllocationList, count: usize) {
+    for _ in 0..count {
+      match self.pop() {
+        Some(page) => unsafe { pool.free_block(page, 0) },
+        None => break
+      }
     }
   }
 
+}
+
+impl PageAllocator {
+
+  const fn new() -> Self {
+    const EMPTY_CACHE: Mutex<PageCache> = Mutex::new(PageCache::new());
+    Self {
+      pool: Mutex::new(AllocationList::new()),
+      caches: [EMPTY_CACHE; MAX_CPUS]
+    }
+  }
+
+  /// Frees pages in the range of [page_round_up(start),end].
+  /// See AllocationList::dealloc_range.
+  pub unsafe fn dealloc_range(&self, start: usize, end: usize) {
+    self.pool.lock().dealloc_range(start, end);
+  }
+
   /// Allocates a page.
+  /// If no pages are free, a user page is evicted to swap to make room.
   /// Returns an option containing the address of the allocated page.
   /// # Arguments
+  /// * 'owner' - What the page is for.
+  #[track_caller]
+  pub fn alloc_page(&self, owner: PageOwner) -> Option<usize> {
+    match self.alloc_pages(0, owner) {
+      Some(page) => Some(page),
+      None => {
+        // The evicted page is still allocated, so only its owner changes.
+        let page = swap::reclaim_page()?;
+        self.record_allocation(page, 0, owner);
+        Some(page)
+      }
+    }
+  }
+
+  /// Allocates 2^order physically contiguous pages.
+  /// The block starts with one reference.
+  /// Returns an option containing the address of the first page. The address is aligned to the size of the block.
+  /// # Arguments
+  /// * 'order' - The order of the block. Must not be larger than MAX_ORDER.
+  /// * 'owner' - What the block is for.
+  #[track_caller]
+  pub fn alloc_pages(&self, order: usize, owner: PageOwner) -> Option<usize> {
+    assert!(order <= MAX_ORDER);
+
+    let cached = if order == 0 { self.alloc_cached_page() } else { None };
+    let block = match cached.or_else(|| self.pool.lock().alloc_pages(order)) {
+      Some(block) => block,
+      None => {
+        // The pages the pool is missing may sit in the caches of the other cpus.
+        self.drain_caches();
+        self.pool.lock().alloc_pages(order)?
+      }
+    };
+
+    REFERENCE_COUNTS[page_number(block)].store(1, Ordering::Relaxed);
+    self.record_allocation(block, order, owner);
+    Some(block)
+  }
+
+  /// Deallocates a page.
+  /// # Arguments
   /// * 'address' - A page address returned from alloc_page.
-  pub fn alloc_page(&mut self) -> Option<usize> {
-    unsafe {
-      let cur = self.head.next.take();
-      match cur {
-        None => {
-          None
-        }
-        Some(page) => {
-          let ret = page.address();
-          self.head.next = page.next.take();
+  #[track_caller]
+  pub unsafe fn dealloc_page(&self, address: usize) {
+    self.dealloc_pages(address, 0);
+  }
 
-          Some(ret)
+  /// Deallocates 2^order pages and merges the block with its free buddies.
+  /// # Arguments
+  /// * 'address' - A block address returned from alloc_pages.
+  /// * 'order' - The order the block was allocated with.
+  #[track_caller]
+  pub unsafe fn dealloc_pages(&self, address: usize, order: usize) {
+    assert!(order <= MAX_ORDER);
+    assert!(page_number(address) % (1 << order) == 0, "dealloc_pages: misaligned block");
+
+    #[cfg(feature = "page_allocator_debug")]
+    self.pool.lock().check_free(address, order);
+
+    REFERENCE_COUNTS[page_number(address)].store(0, Ordering::Relaxed);
+
+    if order == 0 {
+      if let Some(mut cache) = self.current_cache() {
+        if cache.count == CACHE_SIZE {
+          cache.drain(&mut self.pool.lock(), CACHE_BATCH);
         }
+        cache.push(address);
+        return;
       }
     }
+    self.pool.lock().free_block(address, order);
   }
 
-  /// Deallocates a page.
+  /// Adds a reference to an allocated page, for example when a second page directory maps it.
   /// # Arguments
   /// * 'address' - A page address returned from alloc_page.
-  pub unsafe fn dealloc_page(&mut self, address: usize) {
-    // Create the new node.
-    let mut page_allocation_node = AllocationNode::new();
-    // The new node will point to the current head's next.
-    page_allocation_node.next = self.head.next.take();
+  pub fn add_reference(&self, address: usize) {
+    let previous = REFERENCE_COUNTS[page_number(address)].fetch_add(1, Ordering::Relaxed);
+    assert!(previous > 0, "add_reference: page is not allocated");
+  }
+
+  /// Drops a reference to an allocated page and frees the page once no references are left.
+  /// Returns true if the page was freed.
+  /// # Arguments
+  /// * 'address' - A page address returned from alloc_page.
+  #[track_caller]
+  pub unsafe fn remove_reference(&self, address: usize) -> bool {
+    let previous = REFERENCE_COUNTS[page_number(address)].fetch_sub(1, Ordering::AcqRel);
+    assert!(previous > 0, "remove_reference: page is not allocated");
+    if previous == 1 {
+      self.dealloc_page(address);
+      return true;
+    }
+    false
+  }
 
-    // Create a pointer to the start of the next free block.
-    let page_allocation_node_pointer = address as *mut AllocationNode;
-    // Write the new node into that memory.
-    page_allocation_node_pointer.write(page_allocation_node);
+  /// Returns the number of references to an allocated page.
+  pub fn reference_count(&self, address: usize) -> usize {
+    REFERENCE_COUNTS[page_number(address)].load(Ordering::Relaxed) as usize
+  }
 
-    // Set the current head to the new node.
-    self.head.next = Some(&mut *page_allocation_node_pointer);
+  /// The number of free pages, including the pages in the caches.
+  pub fn free_page_count(&self) -> usize {
+    let cached: usize = self.caches.iter().map(|cache| cache.lock().count).sum();
+    self.pool.lock().free_pages + cached
+  }
+
+  /// The number of allocated pages.
+  /// The free pages are counted one lock at a time, so a page that moves from a cache to the pool meanwhile can be
+  /// counted twice. The result is only a snapshot anyway, so it stops at 0 instead of underflowing.
+  pub fn used_page_count(&self) -> usize {
+    let managed_pages = self.pool.lock().managed_pages;
+    managed_pages.saturating_sub(self.free_page_count())
+  }
+
+  /// Returns the cache of the current cpu.
+  /// Returns None with interrupts enabled, since the caller could then move to another cpu.
+  fn current_cache(&self) -> Option<MutexGuard<'_, PageCache>> {
+    if interrupts_enabled() {
+      return None;
+    }
+    Some(self.caches[get_current_cpu_id() as usize].lock())
+  }
+
+  /// Takes a page from the current cpu's cache, refilling the cache from the pool if it is empty.
+  fn alloc_cached_page(&self) -> Option<usize> {
+    let mut cache = self.current_cache()?;
+    if cache.count == 0 {
+      cache.refill(&mut self.pool.lock(), CACHE_BATCH);
+    }
+    cache.pop()
   }
 
-}
\ No newline at end of file
+  /// Returns the pages in every cpu's cache to the pool, so that they can be merged into larger blocks.
+  fn drain_caches(&self) {
+    for cache in self.caches.iter() {
+      cache.lock().drain(&mut self.pool.lock(), CACHE_SIZE);
+    }
+  }
+
+  /// Records the owner and call site of a new block in debug mode.
+  #[track_caller]
+  fn record_allocation(&self, address: usize, order: usize, owner: PageOwner) {
+    #[cfg(feature = "page_allocator_debug")]
+    {
+      self.pool.lock().allocations[page_number(address)] = Allocation {
+        owner: Some(owner),
+        order: order as u8,
+        call_site: Some(Location::caller())
+      };
+    }
+    #[cfg(not(feature = "page_allocator_debug"))]
+    {
+      let _ = (address, order, owner);
+    }
+  }
+
+}
+
+/// Prints the number of free and used pages. In debug mode, also prints the outstanding allocations grouped by owner
+/// and call site.
+/// Control-P on the console calls it.
+pub fn dump() {
+  println!("Pages: {} free, {} used", FREE_PAGE_LIST.free_page_count(), FREE_PAGE_LIST.used_page_count());
+  #[cfg(feature = "page_allocator_debug")]
+  FREE_PAGE_LIST.pool.lock().dump_allocations();
+}
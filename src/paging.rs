@@ -0,0 +1,433 @@
+//! # Paging
+//! The page table types and the paging mode.
+//! By default the kernel uses 32-bit paging, with two levels of 4-byte entries and the types of
+//! x86::bits32::paging. With the pae feature it uses PAE paging instead. PAE entries are 8 bytes and have a
+//! no-execute bit, which user stacks, user heaps and kernel data are mapped with when the cpu supports it.
+//! The PAE types have the names and methods of the 32-bit ones, so the rest of the kernel works with either mode.
+//! With PAE, a PD holds the four page directories of a page directory pointer table in four consecutive pages. It is
+//! indexed like a 32-bit page directory, with an entry for every 2MiB instead of every 4MiB. The pointer table only
+//! depends on where the PD is, so each cpu has one of its own that it fills in when it loads a PD.
+
+use x86::controlregs::{cr3_write, cr4, cr4_write, Cr4};
+use x86::cpuid::CpuId;
+use x86::tlb;
+use boot_options;
+use memory_layout::map_virtual_to_physical;
+use process::get_current_cpu_id;
+
+#[cfg(not(feature = "pae"))]
+pub use x86::bits32::paging::{PAddr, PD, PDEntry, PDFlags, PT, PTEntry, PTFlags};
+#[cfg(feature = "pae")]
+pub use x86::bits32::paging::PAddr;
+#[cfg(feature = "pae")]
+pub use self::pae::{PD, PDEntry, PDFlags, PT, PTEntry, PTFlags};
+
+#[cfg(feature = "pae")]
+use x86::controlregs::cr3;
+#[cfg(feature = "pae")]
+use x86::msr::{IA32_EFER, rdmsr, wrmsr};
+#[cfg(feature = "pae")]
+use acpi::MAX_CPUS;
+#[cfg(feature = "pae")]
+use memory_layout::map_physical_virtual;
+#[cfg(feature = "pae")]
+use mmu::PAGE_SIZE;
+
+/// The bits of a page table entry.
+#[cfg(not(feature = "pae"))]
+pub type EntryBits = u32;
+#[cfg(feature = "pae")]
+pub type EntryBits = u64;
+
+/// The no-execute bit of a page table entry. 32-bit paging has none.
+#[cfg(not(feature = "pae"))]
+pub const NO_EXECUTE_BIT: EntryBits = 0;
+#[cfg(feature = "pae")]
+pub const NO_EXECUTE_BIT: EntryBits = 1 << 63;
+
+/// The order of the page allocator block that holds a PD.
+#[cfg(not(feature = "pae"))]
+pub const PAGE_DIRECTORY_ORDER: usize = 0;
+#[cfg(feature = "pae")]
+pub const PAGE_DIRECTORY_ORDER: usize = 2;
+
+/// Set by init if page directory entries can map large pages.
+static mut LARGE_PAGES: bool = false;
+/// Set by init if the no-execute bit is enabled.
+static mut NO_EXECUTE: bool = false;
+/// Set by init if the global bit is enabled.
+static mut GLOBAL_PAGES: bool = false;
+/// Set by init if supervisor mode execution prevention is enabled.
+static mut SMEP: bool = false;
+/// Set by init if supervisor mode access prevention is enabled.
+static mut SMAP: bool = false;
+
+/// Detects the cpu's paging features and enables the ones the kernel uses.
+/// Called by kmalloc before the kernel page directory is created.
+#[cfg(not(feature = "pae"))]
+pub unsafe fn init() {
+  let cpu_id = CpuId::new();
+  if cpu_id.get_feature_info().map_or(false, |features| features.has_pse()) {
+    cr4_write(cr4() | Cr4::CR4_ENABLE_PSE);
+    LARGE_PAGES = true;
+  }
+  init_global_pages(&cpu_id);
+  init_supervisor_protection(&cpu_id);
+}
+
+/// Detects the cpu's paging features and enables the ones the kernel uses.
+/// Called by kmalloc before the kernel page directory is created.
+#[cfg(feature = "pae")]
+pub unsafe fn init() {
+  /// The no-execute enable bit of IA32_EFER.
+  const EFER_NXE: u64 = 1 << 11;
+
+  let cpu_id = CpuId::new();
+  if !cpu_id.get_feature_info().map_or(false, |features| features.has_pae()) {
+    panic!("paging::init: the pae feature needs a cpu with PAE");
+  }
+  // PAE page directory entries can always map large pages.
+  LARGE_PAGES = true;
+
+  if cpu_id.get_extended_processor_and_feature_identifiers().map_or(false, |features| features.has_execute_disable()) {
+    wrmsr(IA32_EFER, rdmsr(IA32_EFER) | EFER_NXE);
+    NO_EXECUTE = true;
+  }
+  init_global_pages(&cpu_id);
+  init_supervisor_protection(&cpu_id);
+}
+
+/// Enables global pages if the cpu supports them.
+/// A global TLB entry survives cr3 reloads. The kernel mappings are the same in every address space, so making them
+/// global keeps them cached across context switches.
+unsafe fn init_global_pages(cpu_id: &CpuId) {
+  if cpu_id.get_feature_info().map_or(false, |features| features.has_pge()) {
+    cr4_write(cr4() | Cr4::CR4_ENABLE_GLOBAL_PAGES);
+    GLOBAL_PAGES = true;
+  }
+}
+
+/// Enables SMEP and SMAP if the cpu supports them, unless the nosmep or nosmap boot option is set.
+/// With SMEP the kernel faults when it executes a user page. With SMAP it faults when it accesses a user page
+/// outside of a user_memory::with_user_access window.
+unsafe fn init_supervisor_protection(cpu_id: &CpuId) {
+  let features = cpu_id.get_extended_feature_info();
+  if features.as_ref().map_or(false, |features| features.has_smep()) && !boot_options::is_set("nosmep") {
+    cr4_write(cr4() | Cr4::CR4_ENABLE_SMEP);
+    SMEP = true;
+  }
+  if features.as_ref().map_or(false, |features| features.has_smap()) && !boot_options::is_set("nosmap") {
+    cr4_write(cr4() | Cr4::CR4_ENABLE_SMAP);
+    SMAP = true;
+  }
+}
+
+/// Returns true if page directory entries can map large pages.
+pub fn has_large_pages() -> bool {
+  unsafe { LARGE_PAGES }
+}
+
+/// Returns true if SMEP is enabled.
+pub fn has_smep() -> bool {
+  unsafe { SMEP }
+}
+
+/// Returns true if SMAP is enabled.
+pub fn has_smap() -> bool {
+  unsafe { SMAP }
+}
+
+/// The flags that make a page not executable, or no flags if there is no no-execute bit.
+pub fn no_execute() -> PTFlags {
+  if unsafe { NO_EXECUTE } {
+    PTFlags::from_bits_truncate(NO_EXECUTE_BIT)
+  } else {
+    PTFlags::empty()
+  }
+}
+
+/// The flags that make a kernel page global, or no flags if global pages are not enabled.
+/// User pages must never be global, since they differ between address spaces.
+pub fn global() -> PTFlags {
+  if unsafe { GLOBAL_PAGES } {
+    PTFlags::G
+  } else {
+    PTFlags::empty()
+  }
+}
+
+/// Invalidates the current cpu's TLB entry for the page at address.
+/// Reloading cr3 leaves global entries in the TLB, so a kernel mapping that changes must be invalidated with this.
+pub fn invalidate_page(address: usize) {
+  unsafe {
+    tlb::flush(address);
+  }
+}
+
+/// Returns the value for cr3 that makes page_directory the current page directory of a cpu.
+/// With PAE this fills in the cpu's page directory pointer table.
+/// # Arguments
+/// * 'cpu' - The index of the cpu in CPUS.
+/// * 'page_directory' - The page directory.
+#[cfg(not(feature = "pae"))]
+pub fn page_directory_base(_cpu: usize, page_directory: &PD) -> usize {
+  map_virtual_to_physical(page_directory as *const PD as usize)
+}
+
+/// Returns the value for cr3 that makes page_directory the current page directory of a cpu.
+/// With PAE this fills in the cpu's page directory pointer table.
+/// # Arguments
+/// * 'cpu' - The index of the cpu in CPUS.
+/// * 'page_directory' - The page directory.
+#[cfg(feature = "pae")]
+pub fn page_directory_base(cpu: usize, page_directory: &PD) -> usize {
+  let pointer_table = unsafe { &mut POINTER_TABLES[cpu] };
+  pointer_table.point_to(page_directory);
+  map_virtual_to_physical(pointer_table as *const PointerTable as usize)
+}
+
+/// Loads page_directory into cr3 on the current cpu.
+/// Must be called with interrupts disabled.
+pub unsafe fn load_page_directory(page_directory: &PD) {
+  cr3_write(page_directory_base(get_current_cpu_id() as usize, page_directory) as u64);
+}
+
+/// Switches the current cpu from the boot page directory to page_directory.
+#[cfg(not(feature = "pae"))]
+pub unsafe fn enable(page_directory: &PD) {
+  load_page_directory(page_directory);
+}
+
+/// Switches the current cpu from the boot page directory to page_directory.
+#[cfg(feature = "pae")]
+pub unsafe fn enable(page_directory: &PD) {
+  // Setting CR4.PAE makes the cpu read a pointer table at cr3, while cr3 holds a 32-bit page directory until then.
+  // So cr3 first gets a page that works as both: a copy of the boot page directory whose first entries, which map
+  // low memory the kernel does not use at this point, hold the pointer table.
+  let boot_page_directory = &*(map_physical_virtual(cr3() as usize) as *const [u32; 1024]);
+  SWITCH_PAGE.0.copy_from_slice(boot_page_directory);
+  (*(SWITCH_PAGE.0.as_mut_ptr() as *mut PointerTable)).point_to(page_directory);
+
+  cr3_write(map_virtual_to_physical(SWITCH_PAGE.0.as_ptr() as usize) as u64);
+  cr4_write(cr4() | Cr4::CR4_ENABLE_PAE);
+
+  load_page_directory(page_directory);
+}
+
+/// A page directory pointer table.
+#[cfg(feature = "pae")]
+#[repr(C, align(32))]
+#[derive(Copy, Clone)]
+struct PointerTable([u64; 4]);
+
+#[cfg(feature = "pae")]
+impl PointerTable {
+  /// Points the four entries at the four page directories of page_directory.
+  fn point_to(&mut self, page_directory: &PD) {
+    let base = map_virtual_to_physical(page_directory as *const PD as usize);
+    for (index, entry) in self.0.iter_mut().enumerate() {
+      *entry = (base + index * PAGE_SIZE) as u64 | PDFlags::P.bits();
+    }
+  }
+}
+
+#[cfg(feature = "pae")]
+static mut POINTER_TABLES: [PointerTable; MAX_CPUS] = [PointerTable([0; 4]); MAX_CPUS];
+
+/// The page that enable switches to PAE with.
+#[cfg(feature = "pae")]
+#[repr(C, align(4096))]
+struct SwitchPage([u32; 1024]);
+
+#[cfg(feature = "pae")]
+static mut SWITCH_PAGE: SwitchPage = SwitchPage([0; 1024]);
+
+#[cfg(feature = "pae")]
+mod pae {
+  use core::ops::{BitOr, BitOrAssign};
+  use x86::bits32::paging::PAddr;
+
+  /// The address bits of an entry that points to a page table or maps a 4KiB page.
+  const ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+  /// The address bits of an entry that maps a 2MiB page.
+  const LARGE_PAGE_ADDRESS_MASK: u64 = 0x000F_FFFF_FFE0_0000;
+
+  /// Defines a set of entry flags with the methods of the bitflags types it replaces.
+  macro_rules! entry_flags {
+    ($(#[$attribute:meta])* $name:ident { $($(#[$flag_attribute:meta])* $flag:ident = $bit:expr;)* }) => {
+      $(#[$attribute])*
+      #[repr(transparent)]
+      #[derive(Copy, Clone, PartialEq, Eq)]
+      pub struct $name(u64);
+
+      impl $name {
+        $($(#[$flag_attribute])* pub const $flag: $name = $name(1 << $bit);)*
+
+        pub const fn empty() -> $name {
+          $name(0)
+        }
+
+        pub const fn bits(&self) -> u64 {
+          self.0
+        }
+
+        pub const fn from_bits_truncate(bits: u64) -> $name {
+          $name(bits & (0 $(| 1 << $bit)*))
+        }
+
+        pub const fn contains(&self, other: $name) -> bool {
+          self.0 & other.0 == other.0
+        }
+      }
+
+      impl BitOr for $name {
+        type Output = $name;
+
+        fn bitor(self, other: $name) -> $name {
+          $name(self.0 | other.0)
+        }
+      }
+
+      impl BitOrAssign for $name {
+        fn bitor_assign(&mut self, other: $name) {
+          self.0 |= other.0;
+        }
+      }
+    }
+  }
+
+  entry_flags! {
+    /// PAE page directory entry flags.
+    PDFlags {
+      /// Present.
+      P = 0;
+      /// Read/write.
+      RW = 1;
+      /// User/supervisor.
+      US = 2;
+      /// Page-level write-through.
+      PWT = 3;
+      /// Page-level cache disable.
+      PCD = 4;
+      /// Accessed.
+      A = 5;
+      /// Dirty, for a 2MiB page.
+      D = 6;
+      /// Page size; the entry maps a 2MiB page instead of pointing to a page table.
+      PS = 7;
+      /// Global, for a 2MiB page.
+      G = 8;
+      /// Execute disable.
+      XD = 63;
+    }
+  }
+
+  entry_flags! {
+    /// PAE page table entry flags.
+    PTFlags {
+      /// Present.
+      P = 0;
+      /// Read/write.
+      RW = 1;
+      /// User/supervisor.
+      US = 2;
+      /// Page-level write-through.
+      PWT = 3;
+      /// Page-level cache disable.
+      PCD = 4;
+      /// Accessed.
+      A = 5;
+      /// Dirty.
+      D = 6;
+      /// Page attribute table.
+      PAT = 7;
+      /// Global.
+      G = 8;
+      /// Execute disable.
+      XD = 63;
+    }
+  }
+
+  /// A PAE page directory entry.
+  #[repr(transparent)]
+  #[derive(Copy, Clone)]
+  pub struct PDEntry(pub u64);
+
+  impl PDEntry {
+    /// Creates an entry that points to the page table at address, or maps the 2MiB page at address with PS.
+    pub fn new(address: PAddr, flags: PDFlags) -> PDEntry {
+      let mask = if flags.contains(PDFlags::PS) { LARGE_PAGE_ADDRESS_MASK } else { ADDRESS_MASK };
+      let address = address.as_usize() as u64;
+      assert!(address & !mask == 0);
+      PDEntry(address | flags.bits())
+    }
+
+    pub fn address(self) -> PAddr {
+      let mask = if self.flags().contains(PDFlags::PS) { LARGE_PAGE_ADDRESS_MASK } else { ADDRESS_MASK };
+      PAddr::from((self.0 & mask) as u32)
+    }
+
+    pub fn flags(self) -> PDFlags {
+      PDFlags::from_bits_truncate(self.0)
+    }
+
+    pub fn is_present(self) -> bool {
+      self.flags().contains(PDFlags::P)
+    }
+
+    pub fn is_writeable(self) -> bool {
+      self.flags().contains(PDFlags::RW)
+    }
+
+    pub fn is_user_mode_allowed(self) -> bool {
+      self.flags().contains(PDFlags::US)
+    }
+  }
+
+  /// A PAE page table entry.
+  #[repr(transparent)]
+  #[derive(Copy, Clone)]
+  pub struct PTEntry(pub u64);
+
+  impl PTEntry {
+    /// Creates an entry that maps the 4KiB page at address.
+    pub fn new(address: PAddr, flags: PTFlags) -> PTEntry {
+      let address = address.as_usize() as u64;
+      assert!(address & !ADDRESS_MASK == 0);
+      PTEntry(address | flags.bits())
+    }
+
+    pub fn address(self) -> PAddr {
+      PAddr::from((self.0 & ADDRESS_MASK) as u32)
+    }
+
+    pub fn flags(self) -> PTFlags {
+      PTFlags::from_bits_truncate(self.0)
+    }
+
+    pub fn is_present(self) -> bool {
+      self.flags().contains(PTFlags::P)
+    }
+
+    pub fn is_writeable(self) -> bool {
+      self.flags().contains(PTFlags::RW)
+    }
+
+    pub fn is_user_mode_allowed(self) -> bool {
+      self.flags().contains(PTFlags::US)
+    }
+
+    pub fn is_accessed(self) -> bool {
+      self.flags().contains(PTFlags::A)
+    }
+
+    pub fn is_dirty(self) -> bool {
+      self.flags().contains(PTFlags::D)
+    }
+  }
+
+  /// The four page directories of a page directory pointer table.
+  pub type PD = [PDEntry; 2048];
+  /// A PAE page table.
+  pub type PT = [PTEntry; 512];
+}
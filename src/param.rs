@@ -1,4 +1,38 @@
+/// The maximum number of processes.
+pub const MAX_PROCESSES: usize = 24;
+
 pub const NOFILE: usize = 16;
 
 // Change in entry.S as well.
-pub static KERNEL_STACK_SIZE: usize = 16384;
+pub const KERNEL_STACK_SIZE: usize = 16384;
+
+/// The largest size the user stack can grow to.
+pub const MAX_USER_STACK_SIZE: usize = 0x100000;
+
+/// The number of page sized slots in the swap area on the swap disk, ide::SWAP_DISK.
+pub const SWAP_SLOT_COUNT: usize = 4096;
+
+/// The maximum number of mmap areas per process.
+pub const MAX_MEMORY_AREAS: usize = 16;
+
+/// The maximum number of shared memory segments.
+pub const MAX_SHARED_MEMORY_SEGMENTS: usize = 16;
+/// The maximum size of a shared memory segment in pages.
+pub const MAX_SHARED_MEMORY_PAGES: usize = 256;
+
+/// The number of queues of the mlfq scheduling policy.
+pub const MLFQ_QUEUE_COUNT: usize = 4;
+/// The ticks a process runs for in each mlfq queue before it moves down a queue.
+pub const MLFQ_TIME_QUANTA: [u32; MLFQ_QUEUE_COUNT] = [1, 2, 4, 8];
+/// The ticks between the boosts that move every process back to the first mlfq queue.
+pub const MLFQ_BOOST_INTERVAL: u32 = 100;
+
+/// The tickets a process starts with under the stride and lottery scheduling policies.
+pub const DEFAULT_TICKETS: u32 = 100;
+/// The most tickets a process can have.
+pub const MAX_TICKETS: u32 = 10000;
+
+/// The ticks the share test of the stride and lottery scheduling policies runs for, about 10 seconds.
+pub const SHARE_TEST_TICKS: u32 = 1000;
+/// The tickets of the processes the share test runs.
+pub const SHARE_TEST_TICKETS: [u32; 3] = [300, 200, 100];
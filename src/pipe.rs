@@ -1,5 +1,10 @@
+use slab::Slab;
+
 const PIPESIZE: usize = 512;
 
+/// Cache of pipe objects.
+pub static PIPE_CACHE: Slab<Pipe> = Slab::new("pipe", Pipe::new);
+
 #[repr(C)]
 pub struct Pipe {
     // Lock goes here
@@ -9,3 +14,15 @@ pub struct Pipe {
     readopen: i32,
     writeopen: i32,
 }
+
+impl Pipe {
+    pub const fn new() -> Pipe {
+        Pipe {
+            data: [0; PIPESIZE],
+            nread: 0,
+            nwrite: 0,
+            readopen: 0,
+            writeopen: 0,
+        }
+    }
+}
@@ -4,49 +4,59 @@ use types::Pde;
 use ::{panic, param};
 use ::{local_interrupt_controller, mmu};
 use page_allocator;
+use kernel_stack;
 
-use core::{ffi, mem};
-use core::arch::asm;
-use core::ptr::null_mut;
+use core::{ffi, mem, slice};
+use core::arch::global_asm;
+use core::ptr::{self, null, null_mut};
 use x86::bits32::eflags;
 use x86::bits32::eflags::EFlags;
-use x86::bits32::paging::PD;
+use x86::irq;
 use x86::segmentation::Descriptor;
 use acpi::{CPUS, MAX_CPUS};
+use address_space::AddressSpace;
 use arch::TrapFrame;
 use console::print;
-use mmu::{PAGE_SIZE, SegDesc, TaskState};
-use page_allocator::FREE_PAGE_LIST;
-use virtual_memory::{setup_kernel_virtual_memory};
+use memory_layout::{USER_HEAP_LIMIT, USER_STACK_LIMIT, USER_STACK_TOP};
+use mmu::{PAGE_SIZE, SEGMENT_USER_CODE, SEGMENT_USER_DATA, TaskState};
+use param::{KERNEL_STACK_SIZE, MAX_MEMORY_AREAS, MAX_PROCESSES, NOFILE};
+use scheduling::{self, SchedulingState};
+use slab::Slab;
+use spin::MutexGuard;
+use trap::trapret;
+use virtual_memory;
+use vma::{self, VirtualMemoryArea};
 
 static mut PROCESS_ID: usize = 0;
 
+/// The first user process, which adopts the children of processes that exit.
+static mut INIT_PROCESS: *const Process = 0 as *const Process;
 
-unsafe fn trapret() {
-  const x: usize = 0x8;
-  asm!("popal",
-  "pop gs",
-  "pop fs",
-  "pop es",
-  "pop ds",
-  "add {x}, esp",
-  "iret",
-  x = in(reg) x);
-}
+
+/// Cache of process objects.
+pub static PROCESS_CACHE: Slab<Process> = Slab::new("process", Process::new);
+
+const NO_PROCESS: Option<&'static mut Process> = None;
+
+/// The process table. Its lock also guards the state of each process and is held across context switches.
+/// The processes are allocated from PROCESS_CACHE.
+pub type ProcessTable = [Option<&'static mut Process>; MAX_PROCESSES];
 
 lazy_static! {
-    pub static ref PROCESS_TABLE : spin::Mutex<[Option<Process>; 24]> = spin::Mutex::new([Option::None; 24]);
+    pub static ref PROCESS_TABLE : spin::Mutex<ProcessTable> = spin::Mutex::new([NO_PROCESS; MAX_PROCESSES]);
 }
 
 #[derive(Copy, Clone)]
 pub struct Cpu {
   pub(crate) apicid: u8,
-  scheduler: *const Context,
-  ts: mmu::TaskState,
+  /// Where swtch saved the cpu's scheduler while a process runs.
+  scheduler: *mut Context,
+  pub(crate) ts: mmu::TaskState,
   pub gdt: [Descriptor; mmu::SEGMENT_COUNT],
   started: bool,
   ncli: i32,
   intena: i32,
+  /// The process running on the cpu, or null while the cpu runs its scheduler.
   proc: *const Process,
 }
 
@@ -54,7 +64,7 @@ impl Cpu {
   pub const fn new() -> Cpu {
     Cpu {
       apicid: 0,
-      scheduler: 0 as *const Context,
+      scheduler: 0 as *mut Context,
       ts: TaskState::new(),
       gdt: [Descriptor::NULL; mmu::SEGMENT_COUNT],
       started: false,
@@ -65,7 +75,12 @@ impl Cpu {
   }
 }
 
+/// The registers swtch saves when it switches away from a kernel stack.
+/// The caller saved registers are saved by the caller of swtch, and the segment registers are the same in all kernel
+/// code. eip is not saved explicitly, but is the return address swtch was called with. The context is on top of the
+/// stack it describes.
 #[derive(Copy, Clone)]
+#[repr(C)]
 pub struct Context {
   edi: u32,
   esi: u32,
@@ -74,7 +89,7 @@ pub struct Context {
   eip: u32,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 enum ProcessState {
   UNUSED,
   EMBRYO,
@@ -84,95 +99,163 @@ enum ProcessState {
   ZOMBIE
 }
 
-/// Allocates memory for a process and adds it to the process_table.
+extern "C" {
+  /// Saves the current registers on the stack in a Context, stores its address in old and switches to the stack
+  /// and registers of the Context new.
+  fn swtch(old: *mut *mut Context, new: *mut Context);
+}
+
+global_asm!(r#"
+.globl swtch
+swtch:
+  movl 4(%esp), %eax
+  movl 8(%esp), %edx
+
+  # Save the old callee saved registers.
+  pushl %ebp
+  pushl %ebx
+  pushl %esi
+  pushl %edi
+
+  # Switch stacks.
+  movl %esp, (%eax)
+  movl %edx, %esp
+
+  # Load the new callee saved registers.
+  popl %edi
+  popl %esi
+  popl %ebx
+  popl %ebp
+  ret
+"#, options(att_syntax));
+
+/// Allocates a process from PROCESS_CACHE and adds it to the process_table.
+/// The kernel stack is set up so that the process starts in forkret, which returns to trapret with the trap frame.
 ///
-/// Returns a process id if successful.
+/// Returns the index of the process in the process table if successful.
 fn alloc_process() -> Option<usize> {
   let mut pt = PROCESS_TABLE.lock();
 
-  let mut process = Option::None;
-  for process_entry in &mut *pt {
-    match process_entry {
-      None => {
-        process = Some(process_entry);
-        break;
-      }
-      _ => {
-        panic!("Failed to allocate process memory.");
-      }
-    }
-  }
-
-  let mut kernel_stack;
-
-  match process {
-    Some(process_entry) => {
-      let mut stack_pointer: usize;
-      unsafe {
-        PROCESS_ID += 1;
+  let index = pt.iter().position(|process_entry| process_entry.is_none())?;
 
-        kernel_stack = FREE_PAGE_LIST.alloc_page().expect("Failed to get memory for process.");
-      }
-      // PAGE_SIZE should probably be KERNEL_STACK_SIZE in the future
-      stack_pointer = kernel_stack + PAGE_SIZE;
+  let process_id = unsafe {
+    PROCESS_ID += 1;
+    PROCESS_ID
+  };
+  let kernel_stack = kernel_stack::alloc(process_id)?;
+  let process = match PROCESS_CACHE.alloc() {
+    Some(process) => process,
+    None => {
+      kernel_stack::dealloc(kernel_stack);
+      return None;
+    }
+  };
+  let mut stack_pointer = kernel_stack + KERNEL_STACK_SIZE;
 
-      stack_pointer -= mem::size_of::<*mut TrapFrame>();
-      let trap_frame_pointer: *mut TrapFrame = stack_pointer as *mut TrapFrame;
+  // Leave room for the trap frame.
+  stack_pointer -= mem::size_of::<TrapFrame>();
+  let trap_frame_pointer: *mut TrapFrame = stack_pointer as *mut TrapFrame;
 
-      stack_pointer -= mem::size_of::<usize>();
-      unsafe {
-        *(stack_pointer as *mut usize) = trapret as usize; // trapret
-      }
+  // forkret returns to trapret.
+  stack_pointer -= mem::size_of::<usize>();
+  unsafe {
+    *(stack_pointer as *mut usize) = trapret as usize;
+  }
 
-      stack_pointer -= mem::size_of::<Context>();
-      let context_pointer: *mut Context = stack_pointer as *mut Context;
+  stack_pointer -= mem::size_of::<Context>();
+  let context_pointer: *mut Context = stack_pointer as *mut Context;
+  unsafe {
+    *context_pointer = Context {
+      edi: 0,
+      esi: 0,
+      ebx: 0,
+      ebp: 0,
+      eip: forkret as usize as u32,
+    };
+  }
 
-      process_entry.replace(Process {
-        process_state: ProcessState::EMBRYO,
-        kernel_stack: kernel_stack,
-        id: unsafe { PROCESS_ID },
-        page_directory: null_mut(),
-        trap_frame: trap_frame_pointer,
-        context: context_pointer
-      });
+  // The cache's constructor left the other fields as Process::new sets them.
+  process.process_state = ProcessState::EMBRYO;
+  process.kernel_stack = kernel_stack;
+  process.id = process_id;
+  process.trap_frame = trap_frame_pointer;
+  process.context = context_pointer;
+  pt[index] = Some(process);
 
-      unsafe {
-        *process_entry.unwrap().context = Context {
-          edi: 0,
-          esi: 0,
-          ebx: 0,
-          ebp: 0,
-          eip: 13, // forkret
-        };
-      }
-      Some(unsafe { PROCESS_ID - 1 })
-    }
-    None => {
-      None
-    }
-  }
+  Some(index)
 }
 
-#[derive(Copy, Clone)]
 pub struct Process {
   process_state: ProcessState,
-  kernel_stack: usize,
-  id: usize,
-  trap_frame: *mut TrapFrame,
+  /// The lowest address of the kernel stack.
+  pub(crate) kernel_stack: usize,
+  pub(crate) id: usize,
+  pub(crate) trap_frame: *mut TrapFrame,
   context: *mut Context,
-  pub page_directory: *mut PD,
-  /*  pub sz: u32,
-      pub procstate: u32, // Should be enum
-      pub parent: *const Process,
-      pub chan: *const ffi::c_void,
-      pub killed: i32,
-      pub ofile: [*const File; param::NOFILE],
-      pub cwd: *const Inode,
-      pub name: [u8; 16],*/
+  /// The user address space. Only None while the process is being created.
+  pub address_space: Option<AddressSpace>,
+  /// The size of the process's user memory in bytes.
+  pub size: usize,
+  /// Set when the process should exit the next time it runs.
+  pub killed: bool,
+  /// The areas created by mmap.
+  pub memory_areas: [Option<VirtualMemoryArea>; MAX_MEMORY_AREAS],
+  /// The open files, indexed by file descriptor.
+  pub open_files: [*mut File; NOFILE],
+  /// The process that forked this one, or init once that process exits. Null for init.
+  pub parent: *const Process,
+  /// What the process is waiting for while it is sleeping.
+  pub chan: *const ffi::c_void,
+  /// The current directory, or null before the file system is set up.
+  pub cwd: *mut Inode,
+  /// The name of the program, for debugging.
+  pub name: [u8; 16],
+  /// The status passed to exit, for wait.
+  pub exit_status: i32,
+  /// The clock ticks the process has run for.
+  pub ticks: u32,
+  /// What the scheduling policy keeps track of for the process.
+  pub(crate) scheduling: SchedulingState,
 }
 
 unsafe impl Send for Process {}
 
+impl Process {
+  pub const fn new() -> Process {
+    Process {
+      process_state: ProcessState::UNUSED,
+      kernel_stack: 0,
+      id: 0,
+      trap_frame: null_mut(),
+      context: null_mut(),
+      address_space: None,
+      size: 0,
+      killed: false,
+      memory_areas: [None; MAX_MEMORY_AREAS],
+      open_files: [null_mut(); NOFILE],
+      parent: null(),
+      chan: null(),
+      cwd: null_mut(),
+      name: [0; 16],
+      exit_status: 0,
+      ticks: 0,
+      scheduling: SchedulingState::new()
+    }
+  }
+
+  /// Returns true if the process is waiting for a cpu to run it.
+  pub fn is_runnable(&self) -> bool {
+    self.process_state == ProcessState::RUNNABLE
+  }
+
+  /// Returns true if a page fault at virtual_address should be handled by mapping a zeroed page.
+  /// This is the case for the heap below size, which grow_process only reserves, and for the stack, which grows
+  /// down from USER_STACK_TOP as it is used.
+  pub fn is_lazy_address(&self, virtual_address: usize) -> bool {
+    virtual_address < self.size || (USER_STACK_LIMIT <= virtual_address && virtual_address < USER_STACK_TOP)
+  }
+}
+
 pub fn get_current_cpu() -> &'static mut Cpu {
   let flags = unsafe {
     eflags::read()
@@ -197,6 +280,52 @@ pub fn get_current_cpu() -> &'static mut Cpu {
   panic!("Unknown local APIC id!\n");
 }
 
+/// Returns the process running on the current cpu, or None if the cpu is running its scheduler.
+/// Must be called with interrupts disabled.
+pub fn my_process() -> Option<&'static mut Process> {
+  let cpu = get_current_cpu();
+  if cpu.proc.is_null() {
+    return None;
+  }
+  unsafe {
+    Some(&mut *(cpu.proc as *mut Process))
+  }
+}
+
+/// Grows or shrinks the current process's user memory by n bytes, like sbrk.
+/// Growing only reserves address space. Pages are allocated by the page fault handler when they are first used.
+/// Returns the old size, or None if the size would become negative or run into the stack.
+pub fn grow_process(n: isize) -> Option<usize> {
+  let process = my_process()?;
+  let old_size = process.size;
+  let new_size = if n >= 0 {
+    old_size.checked_add(n as usize)?
+  } else {
+    old_size.checked_sub(n.unsigned_abs())?
+  };
+
+  if new_size > USER_HEAP_LIMIT {
+    return None;
+  }
+
+  if new_size < old_size {
+    if let Some(address_space) = process.address_space.as_mut() {
+      address_space.deallocate(old_size, new_size);
+    }
+  }
+
+  process.size = new_size;
+  Some(old_size)
+}
+
+/// Returns true if interrupts are enabled on the current cpu.
+pub fn interrupts_enabled() -> bool {
+  let flags = unsafe {
+    eflags::read()
+  };
+  flags.contains(EFlags::FLAGS_IF)
+}
+
 pub fn get_current_cpu_id() -> u8 {
   let flags = unsafe {
     eflags::read()
@@ -221,17 +350,427 @@ pub fn get_current_cpu_id() -> u8 {
   panic!("Unknown local APIC id!\n");
 }
 
+extern "C" {
+  /// The first user program, which the Makefile links into the kernel from initcode.S.
+  static _binary_target_kernel_initcode_start: u8;
+  /// The address of this symbol is the size of the first user program.
+  static _binary_target_kernel_initcode_size: u8;
+}
+
+/// Sets up the first user process. It runs initcode once a scheduler picks it.
 pub fn user_init() {
   println!("user_init");
 
-  let pid = alloc_process().expect("Could not create user process");
-  let page_directory = setup_kernel_virtual_memory();
-  let mut process = PROCESS_TABLE.lock()[pid].unwrap();
-
-  if page_directory.is_some() {
-    process.page_directory = page_directory.unwrap() as *mut PD;
-  } else {
-    panic!("user_init: out of memory?")
+  let init_code = unsafe {
+    let start = &_binary_target_kernel_initcode_start as *const u8;
+    let size = &_binary_target_kernel_initcode_size as *const u8 as usize;
+    slice::from_raw_parts(start, size)
+  };
+  let pid = create_process(b"initcode", init_code).expect("Could not create user process");
+  unsafe {
+    INIT_PROCESS = PROCESS_TABLE.lock()[pid].as_deref().unwrap();
   }
   println!("user_init: Success.");
-}
\ No newline at end of file
+}
+
+/// Creates a runnable process named name that runs code, which is loaded at address 0 and must fit in a page.
+/// The process is a child of init, which reaps it once it exits. The first process, init itself, has no parent.
+/// Returns the index of the process in the process table, or None if there is no free entry or not enough memory.
+pub fn create_process(name: &[u8], code: &[u8]) -> Option<usize> {
+  let pid = alloc_process()?;
+  let mut process_table = PROCESS_TABLE.lock();
+  let process = process_table[pid].as_deref_mut().unwrap();
+
+  let mut address_space = match AddressSpace::new() {
+    Some(address_space) => address_space,
+    None => {
+      free_process(&mut process_table[pid]);
+      return None;
+    }
+  };
+  virtual_memory::init_user_virtual_memory(address_space.page_directory(), code.as_ptr() as usize, code.len());
+  process.address_space = Some(address_space);
+  process.size = PAGE_SIZE;
+  process.parent = unsafe { INIT_PROCESS };
+  set_name(process, name);
+
+  // Return to the start of the code in user mode, with an empty stack and interrupts enabled.
+  unsafe {
+    process.trap_frame.write_bytes(0, 1);
+    let trap_frame = &mut *process.trap_frame;
+    trap_frame.cs = ((SEGMENT_USER_CODE << 3) | 3) as u16;
+    trap_frame.ds = ((SEGMENT_USER_DATA << 3) | 3) as u16;
+    trap_frame.es = trap_frame.ds;
+    trap_frame.ss = trap_frame.ds;
+    trap_frame.eflags = EFlags::FLAGS_IF.bits();
+    trap_frame.esp = USER_STACK_TOP as u32;
+    trap_frame.eip = 0;
+  }
+
+  process.process_state = ProcessState::RUNNABLE;
+  Some(pid)
+}
+
+/// Runs processes on the current cpu. Each cpu calls scheduler once it is set up, and it never returns.
+/// The scheduler loops, switching to the runnable process the scheduling policy picks. A process switches back
+/// through sched when it gives up the cpu.
+/// The process table lock is held across the switches: the scheduler passes it to the process it switches to, which
+/// passes it back when it calls sched.
+pub fn scheduler() -> ! {
+  let cpu = get_current_cpu();
+  cpu.proc = null();
+
+  loop {
+    // Let interrupts in while the cpu is idle, in case every process is waiting for one.
+    unsafe {
+      irq::enable();
+      irq::disable();
+    }
+
+    let mut process_table = PROCESS_TABLE.lock();
+    let index = match scheduling::pick(&mut process_table) {
+      Some(index) => index,
+      None => {
+        continue;
+      }
+    };
+    let process = process_table[index].as_deref_mut().unwrap();
+
+    // Switch to the chosen process. It is the process's job to release the process table lock and then
+    // reacquire it before switching back.
+    cpu.proc = process;
+    unsafe {
+      virtual_memory::switchuvm(process);
+    }
+    process.process_state = ProcessState::RUNNING;
+
+    unsafe {
+      swtch(&mut cpu.scheduler, process.context);
+      virtual_memory::switchkvm();
+    }
+
+    // The process is done running for now.
+    cpu.proc = null();
+  }
+}
+
+/// Switches from the current process to the cpu's scheduler.
+/// The process table lock must be held through a forgotten guard, and the process's state must have been changed.
+/// The lock is held again, through no guard, once the process is switched back to.
+unsafe fn sched() {
+  let cpu = get_current_cpu();
+  let process = &mut *(cpu.proc as *mut Process);
+  if process.process_state == ProcessState::RUNNING {
+    panic!("sched: process running");
+  }
+
+  swtch(&mut process.context, cpu.scheduler);
+}
+
+/// Gives up the cpu for one scheduling round.
+/// Must be called with interrupts disabled, from a process.
+pub fn yield_cpu() {
+  let process_table = PROCESS_TABLE.lock();
+  let process = my_process().expect("yield_cpu: no process");
+  process.process_state = ProcessState::RUNNABLE;
+
+  mem::forget(process_table);
+  unsafe {
+    sched();
+    PROCESS_TABLE.force_unlock();
+  }
+}
+
+/// Where a new process starts running, on its first switch from the scheduler.
+/// Returns to trapret, which alloc_process placed on the stack.
+extern "C" fn forkret() {
+  // Still holding the process table lock from the scheduler.
+  unsafe {
+    PROCESS_TABLE.force_unlock();
+  }
+}
+
+/// Sets the name of process to the first bytes of name.
+fn set_name(process: &mut Process, name: &[u8]) {
+  let length = name.len().min(process.name.len() - 1);
+  process.name = [0; 16];
+  process.name[..length].copy_from_slice(&name[..length]);
+}
+
+/// Frees the kernel stack and address space of a process that is not running, returns it to PROCESS_CACHE and empties
+/// its process table entry.
+/// Its memory areas, files and current directory must have been released already.
+fn free_process(process_entry: &mut Option<&'static mut Process>) {
+  if let Some(process) = process_entry.take() {
+    kernel_stack::dealloc(process.kernel_stack);
+    unsafe {
+      PROCESS_CACHE.dealloc(process);
+    }
+  }
+}
+
+/// Creates a new process as a copy of the current one. The child shares the parent's pages copy-on-write and
+/// returns 0 from the system call.
+/// Returns the child's process id, or None if there is no free process table entry or not enough memory.
+pub fn fork() -> Option<usize> {
+  let parent = my_process()?;
+  let index = alloc_process()?;
+  let child = {
+    let mut process_table = PROCESS_TABLE.lock();
+    process_table[index].as_deref_mut().unwrap() as *mut Process
+  };
+  let child = unsafe { &mut *child };
+
+  let size = parent.size;
+  child.address_space = parent.address_space.as_mut().and_then(|address_space| address_space.copy_on_write(size));
+  if child.address_space.is_none() {
+    free_process(&mut PROCESS_TABLE.lock()[index]);
+    return None;
+  }
+  if !vma::copy_areas(parent, child) {
+    vma::unmap_all(child);
+    free_process(&mut PROCESS_TABLE.lock()[index]);
+    return None;
+  }
+
+  child.size = parent.size;
+  child.parent = parent;
+  child.name = parent.name;
+  unsafe {
+    ptr::copy_nonoverlapping(parent.trap_frame, child.trap_frame, 1);
+    // fork returns 0 in the child.
+    (*child.trap_frame).eax = 0;
+  }
+
+  for (fd, &file) in parent.open_files.iter().enumerate() {
+    if !file.is_null() {
+      unsafe {
+        (*file).dup();
+      }
+      child.open_files[fd] = file;
+    }
+  }
+  if !parent.cwd.is_null() {
+    unsafe {
+      (*parent.cwd).dup();
+    }
+    child.cwd = parent.cwd;
+  }
+
+  let id = child.id;
+  let _process_table = PROCESS_TABLE.lock();
+  scheduling::fork(parent, child);
+  child.process_state = ProcessState::RUNNABLE;
+  Some(id)
+}
+
+/// Exits the current process with status, which its parent collects with wait.
+/// The process stays a zombie until then. Its children are given to init.
+pub fn exit(status: i32) -> ! {
+  let process = my_process().expect("exit: no process");
+  if process as *const Process == unsafe { INIT_PROCESS } {
+    panic!("init exiting");
+  }
+
+  for file in process.open_files.iter_mut() {
+    if !file.is_null() {
+      unsafe {
+        (**file).close();
+      }
+      *file = null_mut();
+    }
+  }
+  if !process.cwd.is_null() {
+    unsafe {
+      (*process.cwd).put();
+    }
+    process.cwd = null_mut();
+  }
+  // The address space itself is freed by wait, since it is loaded until the process switches to the scheduler.
+  vma::unmap_all(process);
+
+  let mut process_table = PROCESS_TABLE.lock();
+
+  // The parent might be sleeping in wait.
+  wake_sleepers(&mut process_table, process.parent as *const ffi::c_void);
+
+  // Pass abandoned children to init.
+  let init = unsafe { INIT_PROCESS };
+  for entry in process_table.iter_mut() {
+    if let Some(child) = entry {
+      if child.parent == process as *const Process {
+        child.parent = init;
+      }
+    }
+  }
+  wake_sleepers(&mut process_table, init as *const ffi::c_void);
+
+  process.exit_status = status;
+  process.process_state = ProcessState::ZOMBIE;
+
+  // Jump into the scheduler, never to return.
+  mem::forget(process_table);
+  unsafe {
+    sched();
+  }
+  panic!("zombie exit");
+}
+
+/// Waits for a child of the current process to exit.
+/// Returns the child's process id and exit status, or None if the process has no children or is killed while
+/// waiting.
+pub fn wait() -> Option<(usize, i32)> {
+  let process = my_process()?;
+  let mut process_table = PROCESS_TABLE.lock();
+  loop {
+    let mut has_children = false;
+    for entry in process_table.iter_mut() {
+      let zombie = match entry {
+        Some(child) if child.parent == process as *const Process => {
+          has_children = true;
+          child.process_state == ProcessState::ZOMBIE
+        }
+        _ => false
+      };
+      if zombie {
+        let child = entry.as_ref().unwrap();
+        let result = (child.id, child.exit_status);
+        free_process(entry);
+        return Some(result);
+      }
+    }
+
+    if !has_children || process.killed {
+      return None;
+    }
+
+    // Wait for a child to exit. exit wakes the parent up.
+    process_table = sleep(process as *const Process as *const ffi::c_void, process_table);
+  }
+}
+
+/// Kills the process with id. It exits the next time it returns to user mode.
+/// Returns false if there is no such process.
+pub fn kill(id: usize) -> bool {
+  let mut process_table = PROCESS_TABLE.lock();
+  for entry in process_table.iter_mut() {
+    if let Some(process) = entry {
+      if process.id == id && process.process_state != ProcessState::ZOMBIE {
+        process.killed = true;
+        // Wake the process from sleep, so that it notices.
+        if process.process_state == ProcessState::SLEEPING {
+          process.process_state = ProcessState::RUNNABLE;
+        }
+        return true;
+      }
+    }
+  }
+  false
+}
+
+/// Sleeps on chan until wakeup is called with it.
+/// The caller checks the condition it waits for with the process table lock held, and passes the lock in, so that a
+/// wakeup can not be missed. The lock is held again when sleep returns.
+pub fn sleep<'a>(chan: *const ffi::c_void, process_table: MutexGuard<'a, ProcessTable>) -> MutexGuard<'a, ProcessTable> {
+  let process = my_process().expect("sleep: no process");
+  process.chan = chan;
+  process.process_state = ProcessState::SLEEPING;
+
+  mem::forget(process_table);
+  unsafe {
+    sched();
+  }
+  process.chan = null();
+
+  // sched returned with the lock held but no guard, so exchange the lock for a guard.
+  unsafe {
+    PROCESS_TABLE.force_unlock();
+  }
+  PROCESS_TABLE.lock()
+}
+
+/// Wakes up all processes sleeping on chan.
+pub fn wakeup(chan: *const ffi::c_void) {
+  wake_sleepers(&mut PROCESS_TABLE.lock(), chan);
+}
+
+/// Wakes up all processes sleeping on chan. The process table lock must be held.
+fn wake_sleepers(process_table: &mut ProcessTable, chan: *const ffi::c_void) {
+  for entry in process_table.iter_mut() {
+    if let Some(process) = entry {
+      if process.process_state == ProcessState::SLEEPING && process.chan == chan {
+        process.process_state = ProcessState::RUNNABLE;
+      }
+    }
+  }
+}
+
+/// Charges the current process for a clock tick.
+/// Returns true if the scheduling policy wants it to give up the cpu.
+pub fn tick() -> bool {
+  let current = match my_process() {
+    Some(process) => {
+      process.ticks += 1;
+      process as *const Process
+    }
+    None => {
+      return false;
+    }
+  };
+
+  let mut process_table = PROCESS_TABLE.lock();
+  match process_table.iter().position(|entry| entry.as_deref().map_or(false, |process| process as *const Process == current)) {
+    Some(index) => scheduling::tick(&mut process_table, index),
+    None => false
+  }
+}
+
+/// Lets the scheduling policy act on a clock tick. ticks is the number of ticks since boot.
+/// With the share_test feature, this is also where the share test checks its processes.
+pub fn clock(ticks: u32) {
+  let mut process_table = PROCESS_TABLE.lock();
+  scheduling::clock(&mut process_table, ticks);
+  #[cfg(feature = "share_test")]
+  scheduling::share_test::check(&mut process_table, ticks);
+}
+
+/// Sets the scheduling priority of the process with id.
+/// Returns false if there is no such process, or the scheduling policy does not have the priority.
+pub fn set_priority(id: usize, priority: i32) -> bool {
+  let mut process_table = PROCESS_TABLE.lock();
+  match process_table.iter_mut().flatten().find(|process| process.id == id) {
+    Some(process) => scheduling::set_priority(process, priority),
+    None => false
+  }
+}
+
+/// Sets the number of tickets of the process with id, for the proportional share scheduling policies.
+/// Returns false if there is no such process, or the scheduling policy does not use tickets or can not give that many.
+pub fn set_tickets(id: usize, tickets: i32) -> bool {
+  let mut process_table = PROCESS_TABLE.lock();
+  match process_table.iter_mut().flatten().find(|process| process.id == id) {
+    Some(process) => scheduling::set_tickets(process, tickets),
+    None => false
+  }
+}
+
+/// Returns the scheduling priority of the process with id, or None if there is no such process or the scheduling
+/// policy has no priorities.
+pub fn priority(id: usize) -> Option<i32> {
+  let process_table = PROCESS_TABLE.lock();
+  let process = process_table.iter().flatten().find(|process| process.id == id)?;
+  scheduling::priority(process)
+}
+
+/// Prints a process listing, like procdump, with the scheduling policy's state of each process.
+/// Control-P on the console calls it.
+pub fn dump() {
+  let process_table = PROCESS_TABLE.lock();
+  for process in process_table.iter().flatten() {
+    let name_length = process.name.iter().position(|&byte| byte == 0).unwrap_or(process.name.len());
+    let name = core::str::from_utf8(&process.name[..name_length]).unwrap_or("?");
+    print!("{} {:?} {} ticks {}", process.id, process.process_state, name, process.ticks);
+    scheduling::dump(process);
+    println!();
+  }
+}
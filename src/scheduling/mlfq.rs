@@ -0,0 +1,128 @@
+//! # Multi-Level Feedback Queue
+//! Every process is in one of MLFQ_QUEUE_COUNT queues, 0 having the highest priority. The scheduler runs a process
+//! from the highest priority queue that has a runnable one, and the processes of a queue take turns.
+//! A process starts in queue 0. Once it has run for the time quantum of its queue, MLFQ_TIME_QUANTA[queue] ticks in
+//! total, it moves down a queue. Sleeping does not reset what it has used, so a process can not stay up by giving up
+//! the cpu just before its quantum ends. A running process also gives up the cpu when a process in a higher priority
+//! queue becomes runnable.
+//! Every MLFQ_BOOST_INTERVAL ticks all processes move back to queue 0, so that the processes in low queues do not
+//! starve.
+
+use param::{MLFQ_BOOST_INTERVAL, MLFQ_QUEUE_COUNT, MLFQ_TIME_QUANTA};
+use process::{Process, ProcessTable};
+
+#[derive(Copy, Clone)]
+pub struct SchedulingState {
+  /// The queue the process is in.
+  queue: usize,
+  /// The ticks the process has run for since it entered its queue.
+  quantum_used: u32,
+  /// When the process was last picked, counted in picks. The process of a queue picked least recently runs next.
+  last_picked: u64,
+  /// The ticks the process has run for in each queue.
+  residency: [u32; MLFQ_QUEUE_COUNT],
+}
+
+impl SchedulingState {
+  pub const fn new() -> SchedulingState {
+    SchedulingState {
+      queue: 0,
+      quantum_used: 0,
+      last_picked: 0,
+      residency: [0; MLFQ_QUEUE_COUNT],
+    }
+  }
+}
+
+/// The number of picks so far.
+static mut PICKS: u64 = 0;
+
+/// Picks the runnable process to run next.
+/// Returns its index in the process table, or None if no process is runnable.
+pub fn pick(process_table: &mut ProcessTable) -> Option<usize> {
+  let index = process_table.iter()
+    .enumerate()
+    .filter_map(|(index, entry)| match entry {
+      Some(process) if process.is_runnable() => Some((index, process.scheduling.queue, process.scheduling.last_picked)),
+      _ => None
+    })
+    .min_by_key(|&(_, queue, last_picked)| (queue, last_picked))
+    .map(|(index, _, _)| index)?;
+
+  let state = &mut process_table[index].as_mut().unwrap().scheduling;
+  unsafe {
+    PICKS += 1;
+    state.last_picked = PICKS;
+  }
+  Some(index)
+}
+
+/// Sets up the scheduling state of a child created by fork. The child starts in queue 0, like any new process.
+pub fn fork(_parent: &Process, _child: &mut Process) {
+}
+
+/// Charges the process at index in the process table, which is running, for a clock tick.
+/// Returns true if it should give up the cpu, because it used up its quantum or a process with a higher priority is
+/// runnable.
+pub fn tick(process_table: &mut ProcessTable, index: usize) -> bool {
+  let state = &mut process_table[index].as_mut().unwrap().scheduling;
+  state.quantum_used += 1;
+  state.residency[state.queue] += 1;
+  if state.quantum_used >= MLFQ_TIME_QUANTA[state.queue] {
+    if state.queue + 1 < MLFQ_QUEUE_COUNT {
+      state.queue += 1;
+    }
+    state.quantum_used = 0;
+    return true;
+  }
+
+  let queue = state.queue;
+  process_table.iter().any(|entry| match entry {
+    Some(process) => process.is_runnable() && process.scheduling.queue < queue,
+    None => false
+  })
+}
+
+/// Called on every clock tick, with the number of ticks since boot. Boosts all processes every
+/// MLFQ_BOOST_INTERVAL ticks.
+pub fn clock(process_table: &mut ProcessTable, ticks: u32) {
+  if ticks % MLFQ_BOOST_INTERVAL != 0 {
+    return;
+  }
+
+  for entry in process_table.iter_mut() {
+    if let Some(process) = entry {
+      process.scheduling.queue = 0;
+      process.scheduling.quantum_used = 0;
+    }
+  }
+}
+
+/// Moves process to the queue priority, with a fresh quantum.
+/// Returns false if there is no such queue.
+pub fn set_priority(process: &mut Process, priority: i32) -> bool {
+  if priority < 0 || priority as usize >= MLFQ_QUEUE_COUNT {
+    return false;
+  }
+  process.scheduling.queue = priority as usize;
+  process.scheduling.quantum_used = 0;
+  true
+}
+
+/// Returns the queue of process.
+pub fn priority(process: &Process) -> Option<i32> {
+  Some(process.scheduling.queue as i32)
+}
+
+/// mlfq does not use tickets. Returns false.
+pub fn set_tickets(_process: &mut Process, _tickets: i32) -> bool {
+  false
+}
+
+/// Prints the queue of process and the ticks it has run for in each queue, for a process listing.
+pub fn dump(process: &Process) {
+  print!(" queue {} ticks", process.scheduling.queue);
+  for ticks in process.scheduling.residency.iter() {
+    print!(" {}", ticks);
+  }
+}
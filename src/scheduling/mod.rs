@@ -0,0 +1,31 @@
+//! # Scheduling Policies
+//! Decides which runnable process a cpu's scheduler runs next, and when the running process gives up the cpu.
+//! The policy is chosen at build time with a feature:
+//! * none - Round robin. The runnable processes take turns, one tick each.
+//! * mlfq - A multi-level feedback queue, which favours processes that give up the cpu before their time is up.
+//! * stride - Stride scheduling, which gives each process a share of the cpu proportional to its tickets.
+//! * lottery - Lottery scheduling, the randomized variant of stride scheduling. Implies stride.
+//!
+//! The share_test feature adds a boot-time test of the cpu shares that stride or lottery scheduling gives.
+//!
+//! Every policy provides the same functions. They are called with the process table lock held.
+
+#[cfg(all(feature = "mlfq", feature = "stride"))]
+compile_error!("Only one of the mlfq and stride scheduling policies can be enabled.");
+
+#[cfg(not(any(feature = "mlfq", feature = "stride")))]
+mod round_robin;
+#[cfg(not(any(feature = "mlfq", feature = "stride")))]
+pub use self::round_robin::*;
+
+#[cfg(feature = "mlfq")]
+mod mlfq;
+#[cfg(feature = "mlfq")]
+pub use self::mlfq::*;
+
+#[cfg(feature = "stride")]
+mod stride;
+#[cfg(feature = "stride")]
+pub use self::stride::*;
+#[cfg(feature = "share_test")]
+pub mod share_test;
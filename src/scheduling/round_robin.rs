@@ -0,0 +1,63 @@
+//! # Round Robin
+//! Runs the runnable processes in process table order, each for one tick.
+
+use process::{Process, ProcessTable};
+
+/// Round robin keeps nothing per process.
+#[derive(Copy, Clone)]
+pub struct SchedulingState;
+
+impl SchedulingState {
+  pub const fn new() -> SchedulingState {
+    SchedulingState
+  }
+}
+
+/// The process table index picked last. Picking the next runnable process after it gives every process its turn.
+static mut LAST_PICKED: usize = 0;
+
+/// Picks the runnable process to run next.
+/// Returns its index in the process table, or None if no process is runnable.
+pub fn pick(process_table: &mut ProcessTable) -> Option<usize> {
+  let count = process_table.len();
+  unsafe {
+    let index = (1..=count)
+      .map(|offset| (LAST_PICKED + offset) % count)
+      .find(|&index| process_table[index].as_deref().map_or(false, Process::is_runnable))?;
+    LAST_PICKED = index;
+    Some(index)
+  }
+}
+
+/// Sets up the scheduling state of a child created by fork.
+pub fn fork(_parent: &Process, _child: &mut Process) {
+}
+
+/// Charges the process at index in the process table, which is running, for a clock tick.
+/// Returns true if it should give up the cpu, which it does on every tick.
+pub fn tick(_process_table: &mut ProcessTable, _index: usize) -> bool {
+  true
+}
+
+/// Called on every clock tick, with the number of ticks since boot.
+pub fn clock(_process_table: &mut ProcessTable, _ticks: u32) {
+}
+
+/// Round robin has no priorities. Returns false.
+pub fn set_priority(_process: &mut Process, _priority: i32) -> bool {
+  false
+}
+
+/// Round robin has no priorities. Returns None.
+pub fn priority(_process: &Process) -> Option<i32> {
+  None
+}
+
+/// Round robin does not use tickets. Returns false.
+pub fn set_tickets(_process: &mut Process, _tickets: i32) -> bool {
+  false
+}
+
+/// Prints the scheduling state of process for a process listing.
+pub fn dump(_process: &Process) {
+}
@@ -0,0 +1,75 @@
+//! # Share Test
+//! Checks that the stride and lottery scheduling policies give processes cpu shares proportional to their tickets.
+//! Built with the share_test feature, which implies stride. start creates a process spinning in a loop for each of
+//! SHARE_TEST_TICKETS, with those tickets. After SHARE_TEST_TICKS ticks, check compares the ticks each of them ran for
+//! with its share of their tickets, prints the result and kills them. init, their parent, then reaps them.
+
+use param::{SHARE_TEST_TICKETS, SHARE_TEST_TICKS};
+use process::{self, PROCESS_TABLE, ProcessTable};
+use super::{set_tickets, tickets};
+
+/// How far, in percentage points, the cpu share of a test process may be from its share of the tickets.
+#[cfg(not(feature = "lottery"))]
+const TOLERANCE: u32 = 2;
+#[cfg(feature = "lottery")]
+const TOLERANCE: u32 = 5;
+
+const NO_PROCESS: usize = 0;
+
+/// The code of the test processes: jmp to itself.
+const SPIN: [u8; 2] = [0xeb, 0xfe];
+
+/// The process ids of the test processes. Only written before the scheduler starts.
+static mut TEST_PROCESSES: [usize; SHARE_TEST_TICKETS.len()] = [NO_PROCESS; SHARE_TEST_TICKETS.len()];
+
+/// Creates the test processes.
+/// Must be called after user_init, so that init adopts them, and before the first scheduler starts.
+pub fn start() {
+  for (i, &test_tickets) in SHARE_TEST_TICKETS.iter().enumerate() {
+    let index = match process::create_process(b"sharetest", &SPIN) {
+      Some(index) => index,
+      None => {
+        println!("share test: could not create a process");
+        return;
+      }
+    };
+
+    let mut process_table = PROCESS_TABLE.lock();
+    let process = process_table[index].as_mut().unwrap();
+    set_tickets(process, test_tickets as i32);
+    unsafe {
+      TEST_PROCESSES[i] = process.id;
+    }
+  }
+  println!("share test: running for {} ticks", SHARE_TEST_TICKS);
+}
+
+/// Checks the shares of the test processes once the test has run for SHARE_TEST_TICKS ticks.
+pub fn check(process_table: &mut ProcessTable, ticks: u32) {
+  let test_processes = unsafe { TEST_PROCESSES };
+  if ticks != SHARE_TEST_TICKS || test_processes[0] == NO_PROCESS {
+    return;
+  }
+
+  let mut total_ticks = 0;
+  let mut total_tickets = 0;
+  for process in process_table.iter().flatten().filter(|process| test_processes.contains(&process.id)) {
+    total_ticks += process.ticks;
+    total_tickets += tickets(process);
+  }
+  if total_ticks == 0 {
+    println!("share test: FAILED, the test processes did not run");
+    return;
+  }
+
+  let mut passed = true;
+  for process in process_table.iter_mut().flatten().filter(|process| test_processes.contains(&process.id)) {
+    let expected = tickets(process) * 100 / total_tickets;
+    let share = process.ticks * 100 / total_ticks;
+    println!("share test: pid {} tickets {} ticks {} share {}% expected {}%",
+             process.id, tickets(process), process.ticks, share, expected);
+    passed &= share + TOLERANCE >= expected && share <= expected + TOLERANCE;
+    process.killed = true;
+  }
+  println!("share test: {}", if passed { "passed" } else { "FAILED" });
+}
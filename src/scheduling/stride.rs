@@ -0,0 +1,147 @@
+//! # Stride Scheduling
+//! Gives each process a share of the cpu proportional to its tickets, which it sets with settickets.
+//! Every process has a stride, STRIDE_ONE divided by its tickets, and a pass. The scheduler runs the runnable process
+//! with the lowest pass for a tick, and then advances its pass by its stride. So a process with twice the tickets
+//! runs twice as often.
+//! A process that was not runnable for a while would have fallen behind and monopolize the cpu until it caught up.
+//! Its pass is therefore moved up to the lowest pass of the runnable processes when it is picked.
+//!
+//! With the lottery feature, the scheduler instead draws a ticket from all the runnable processes' tickets and runs
+//! the process holding it. The shares then only converge to the ticket ratio over time.
+
+use param::{DEFAULT_TICKETS, MAX_TICKETS};
+use process::{Process, ProcessTable};
+
+/// The stride of a process with one ticket.
+const STRIDE_ONE: u64 = 1 << 20;
+
+#[derive(Copy, Clone)]
+pub struct SchedulingState {
+  tickets: u32,
+  /// STRIDE_ONE / tickets.
+  stride: u64,
+  /// The virtual time the process has run for.
+  pass: u64,
+}
+
+impl SchedulingState {
+  pub const fn new() -> SchedulingState {
+    SchedulingState {
+      tickets: DEFAULT_TICKETS,
+      stride: STRIDE_ONE / DEFAULT_TICKETS as u64,
+      pass: 0,
+    }
+  }
+}
+
+/// The pass of the process picked last, which is the lowest pass of the runnable processes at that time.
+static mut GLOBAL_PASS: u64 = 0;
+
+/// Picks the runnable process with the lowest pass to run next.
+/// Returns its index in the process table, or None if no process is runnable.
+#[cfg(not(feature = "lottery"))]
+pub fn pick(process_table: &mut ProcessTable) -> Option<usize> {
+  let global_pass = unsafe { GLOBAL_PASS };
+  let (index, pass) = process_table.iter()
+    .enumerate()
+    .filter_map(|(index, entry)| match entry {
+      Some(process) if process.is_runnable() => Some((index, process.scheduling.pass.max(global_pass))),
+      _ => None
+    })
+    .min_by_key(|&(_, pass)| pass)?;
+
+  process_table[index].as_mut().unwrap().scheduling.pass = pass;
+  unsafe {
+    GLOBAL_PASS = pass;
+  }
+  Some(index)
+}
+
+/// The state of the random number generator of the lottery.
+#[cfg(feature = "lottery")]
+static mut RANDOM_STATE: u32 = 0x2545F491;
+
+/// Returns a pseudo random number, from a xorshift generator.
+#[cfg(feature = "lottery")]
+fn random() -> u32 {
+  unsafe {
+    RANDOM_STATE ^= RANDOM_STATE << 13;
+    RANDOM_STATE ^= RANDOM_STATE >> 17;
+    RANDOM_STATE ^= RANDOM_STATE << 5;
+    RANDOM_STATE
+  }
+}
+
+/// Picks the runnable process holding a ticket drawn at random from the runnable processes' tickets.
+/// Returns its index in the process table, or None if no process is runnable.
+#[cfg(feature = "lottery")]
+pub fn pick(process_table: &mut ProcessTable) -> Option<usize> {
+  let runnable = || process_table.iter()
+    .enumerate()
+    .filter_map(|(index, entry)| match entry {
+      Some(process) if process.is_runnable() => Some((index, process.scheduling.tickets)),
+      _ => None
+    });
+
+  let total: u32 = runnable().map(|(_, tickets)| tickets).sum();
+  if total == 0 {
+    return None;
+  }
+
+  let mut winner = random() % total;
+  for (index, tickets) in runnable() {
+    if winner < tickets {
+      return Some(index);
+    }
+    winner -= tickets;
+  }
+  None
+}
+
+/// Sets up the scheduling state of a child created by fork. The child has the parent's tickets and pass.
+pub fn fork(parent: &Process, child: &mut Process) {
+  child.scheduling = parent.scheduling;
+}
+
+/// Charges the process at index in the process table, which is running, for a clock tick by advancing its pass.
+/// Returns true, so that the next process is picked on every tick.
+pub fn tick(process_table: &mut ProcessTable, index: usize) -> bool {
+  let state = &mut process_table[index].as_mut().unwrap().scheduling;
+  state.pass += state.stride;
+  true
+}
+
+/// Called on every clock tick, with the number of ticks since boot.
+pub fn clock(_process_table: &mut ProcessTable, _ticks: u32) {
+}
+
+/// Stride scheduling has no priorities. Returns false.
+pub fn set_priority(_process: &mut Process, _priority: i32) -> bool {
+  false
+}
+
+/// Stride scheduling has no priorities. Returns None.
+pub fn priority(_process: &Process) -> Option<i32> {
+  None
+}
+
+/// Gives process tickets tickets.
+/// Returns false if tickets is not between 1 and MAX_TICKETS.
+pub fn set_tickets(process: &mut Process, tickets: i32) -> bool {
+  if tickets < 1 || tickets as u32 > MAX_TICKETS {
+    return false;
+  }
+  process.scheduling.tickets = tickets as u32;
+  process.scheduling.stride = STRIDE_ONE / tickets as u64;
+  true
+}
+
+/// Returns the tickets of process.
+pub fn tickets(process: &Process) -> u32 {
+  process.scheduling.tickets
+}
+
+/// Prints the tickets and pass of process, for a process listing.
+pub fn dump(process: &Process) {
+  print!(" tickets {} pass {}", process.scheduling.tickets, process.scheduling.pass);
+}
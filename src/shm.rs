@@ -0,0 +1,222 @@
+//! # Shared Memory
+//! System V style shared memory segments.
+//! shmget creates a segment and allocates its zeroed pages, which the segment holds a reference to. shmat adds a
+//! shared memory area to the process. The area maps the segment's pages on its first use, adding a reference to each
+//! page, so the same physical pages appear in every attached page directory.
+//! A segment counts the areas that map it. Like System V, shmctl(IPC_RMID) only marks a segment for removal. Its
+//! pages are released once the last area is unmapped, by shmdt, munmap or when the process goes away.
+
+use spin::Mutex;
+use mmu::{page_round_up, PAGE_SIZE};
+use page_allocator::{FREE_PAGE_LIST, PageOwner};
+use param::{MAX_SHARED_MEMORY_PAGES, MAX_SHARED_MEMORY_SEGMENTS};
+use process::Process;
+use user_memory::UserData;
+use vma::{self, PROT_READ, PROT_WRITE};
+
+/// The key that always creates a new segment.
+pub const IPC_PRIVATE: i32 = 0;
+
+// shmget flags.
+pub const IPC_CREAT: i32 = 0x200;
+pub const IPC_EXCL: i32 = 0x400;
+
+// shmat flags.
+pub const SHM_RDONLY: i32 = 0x1000;
+
+// shmctl commands.
+pub const IPC_RMID: i32 = 0;
+pub const IPC_STAT: i32 = 2;
+
+/// The segment information returned by shmctl(IPC_STAT).
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct SharedMemoryStatus {
+  pub key: i32,
+  /// The size of the segment in bytes.
+  pub size: u32,
+  /// The number of areas that map the segment.
+  pub attaches: u32
+}
+
+unsafe impl UserData for SharedMemoryStatus {}
+
+#[derive(Copy, Clone)]
+struct Segment {
+  in_use: bool,
+  key: i32,
+  /// The size in bytes, rounded up to whole pages.
+  size: usize,
+  /// The kernel virtual addresses of the segment's pages.
+  pages: [usize; MAX_SHARED_MEMORY_PAGES],
+  attaches: usize,
+  /// Set by IPC_RMID. The segment is released when attaches drops to 0.
+  removed: bool
+}
+
+impl Segment {
+
+  const fn new() -> Self {
+    Self {
+      in_use: false,
+      key: IPC_PRIVATE,
+      size: 0,
+      pages: [0; MAX_SHARED_MEMORY_PAGES],
+      attaches: 0,
+      removed: false
+    }
+  }
+
+  fn page_count(&self) -> usize {
+    self.size / PAGE_SIZE
+  }
+
+  /// Drops the segment's reference to each page and frees the entry.
+  fn release(&mut self) {
+    for &page in &self.pages[..self.page_count()] {
+      unsafe {
+        FREE_PAGE_LIST.remove_reference(page);
+      }
+    }
+    *self = Segment::new();
+  }
+
+}
+
+static SEGMENTS: Mutex<[Segment; MAX_SHARED_MEMORY_SEGMENTS]> = Mutex::new([Segment::new(); MAX_SHARED_MEMORY_SEGMENTS]);
+
+/// Returns the id of the segment for key, creating it if needed, like shmget.
+/// # Arguments
+/// * 'key' - IPC_PRIVATE for a new segment, or a key shared by the processes that use the segment.
+/// * 'size' - The size of the segment in bytes. An existing segment must be at least this large.
+/// * 'flags' - IPC_CREAT to create the segment if there is none for key, and IPC_EXCL to fail if there is one.
+/// Returns None if the segment does not exist or can not be created.
+pub fn get(key: i32, size: usize, flags: i32) -> Option<usize> {
+  let mut segments = SEGMENTS.lock();
+
+  if key != IPC_PRIVATE {
+    let existing = segments.iter().position(|segment| segment.in_use && !segment.removed && segment.key == key);
+    if let Some(id) = existing {
+      if flags & IPC_CREAT != 0 && flags & IPC_EXCL != 0 || size > segments[id].size {
+        return None;
+      }
+      return Some(id);
+    }
+    if flags & IPC_CREAT == 0 {
+      return None;
+    }
+  }
+
+  let size = page_round_up(size);
+  if size == 0 || size > MAX_SHARED_MEMORY_PAGES * PAGE_SIZE {
+    return None;
+  }
+
+  let id = segments.iter().position(|segment| !segment.in_use)?;
+  let segment = &mut segments[id];
+  segment.in_use = true;
+  segment.key = key;
+  segment.size = size;
+  for index in 0..segment.page_count() {
+    let page = match FREE_PAGE_LIST.alloc_page(PageOwner::SharedMemory) {
+      Some(page) => page,
+      None => {
+        segment.size = index * PAGE_SIZE;
+        segment.release();
+        return None;
+      }
+    };
+    unsafe {
+      (page as *mut u8).write_bytes(0, PAGE_SIZE);
+    }
+    segment.pages[index] = page;
+  }
+  Some(id)
+}
+
+/// Attaches a segment to the process, like shmat.
+/// # Arguments
+/// * 'address' - The page aligned address to attach the segment at, or 0 to let the kernel choose.
+/// * 'flags' - SHM_RDONLY to attach the segment read-only.
+/// Returns the address of the segment in the process, or None if the segment does not exist or there is no room.
+pub fn attach(process: &mut Process, id: usize, address: usize, flags: i32) -> Option<usize> {
+  let size = {
+    let segments = SEGMENTS.lock();
+    let segment = segments.get(id)?;
+    if !segment.in_use || segment.removed {
+      return None;
+    }
+    segment.size
+  };
+
+  let protection = if flags & SHM_RDONLY != 0 {
+    PROT_READ
+  } else {
+    PROT_READ | PROT_WRITE
+  };
+  vma::map_shared_memory(process, address, size, protection, id)
+}
+
+/// Detaches the segment attached at address, like shmdt.
+/// Returns false if no segment is attached at address.
+pub fn detach(process: &mut Process, address: usize) -> bool {
+  vma::unmap_shared_memory(process, address)
+}
+
+/// Runs a shmctl command on a segment.
+/// IPC_RMID marks the segment for removal. IPC_STAT writes the segment's information to status.
+/// Returns false if the segment does not exist or the command is unknown.
+pub fn control(id: usize, command: i32, status: &mut SharedMemoryStatus) -> bool {
+  let mut segments = SEGMENTS.lock();
+  let segment = match segments.get_mut(id) {
+    Some(segment) if segment.in_use => segment,
+    _ => {
+      return false;
+    }
+  };
+
+  match command {
+    IPC_RMID => {
+      segment.removed = true;
+      if segment.attaches == 0 {
+        segment.release();
+      }
+      true
+    }
+    IPC_STAT => {
+      *status = SharedMemoryStatus {
+        key: segment.key,
+        size: segment.size as u32,
+        attaches: segment.attaches as u32
+      };
+      true
+    }
+    _ => false
+  }
+}
+
+/// Counts a new area that maps the segment.
+pub fn attach_area(id: usize) {
+  SEGMENTS.lock()[id].attaches += 1;
+}
+
+/// Uncounts an area that mapped the segment. The last area of a removed segment releases it.
+pub fn detach_area(id: usize) {
+  let mut segments = SEGMENTS.lock();
+  let segment = &mut segments[id];
+  segment.attaches -= 1;
+  if segment.attaches == 0 && segment.removed {
+    segment.release();
+  }
+}
+
+/// Returns the page at index in the segment with a new reference for the caller's page table.
+pub fn get_page(id: usize, index: usize) -> Option<usize> {
+  let segments = SEGMENTS.lock();
+  let segment = &segments[id];
+  if index >= segment.page_count() {
+    return None;
+  }
+  FREE_PAGE_LIST.add_reference(segment.pages[index]);
+  Some(segment.pages[index])
+}
@@ -0,0 +1,238 @@
+//! # Slab
+//! Object caches for fixed size kernel objects.
+//! A cache gets blocks of pages, called slabs, from the page allocator. A slab starts with a SlabHeader and the rest
+//! of it is cut into objects. Since the page allocator aligns blocks to their size, the slab of an object is found by
+//! rounding the object's address down to the slab size.
+//! A freed object goes back onto the free list of its slab. A slab with no objects in use is returned to the page
+//! allocator, unless it is the last slab of the cache.
+
+use core::marker::PhantomData;
+use core::mem;
+use core::ptr::{drop_in_place, null_mut};
+use spin::Mutex;
+use file::{FILE_CACHE, INODE_CACHE};
+use mmu::PAGE_SIZE;
+use page_allocator::{FREE_PAGE_LIST, order_for_size, PageOwner};
+use pipe::PIPE_CACHE;
+use process::PROCESS_CACHE;
+
+/// The minimum number of objects that fit in a slab. Large objects get multi-page slabs.
+const MIN_OBJECTS_PER_SLAB: usize = 8;
+
+/// A cache of objects of type T.
+pub struct Slab<T> {
+  name: &'static str,
+  constructor: fn() -> T,
+  slabs: Mutex<SlabList>,
+  object_type: PhantomData<T>
+}
+
+/// Counters kept for each cache.
+#[derive(Copy, Clone)]
+pub struct SlabStatistics {
+  /// Objects handed out since the cache was created.
+  pub allocations: usize,
+  /// Objects returned since the cache was created.
+  pub frees: usize,
+  /// Slabs currently owned by the cache.
+  pub slabs: usize,
+  /// The number of objects that fit in one slab.
+  pub objects_per_slab: usize
+}
+
+struct SlabList {
+  head: *mut SlabHeader,
+  statistics: SlabStatistics
+}
+
+#[repr(C)]
+struct SlabHeader {
+  next: *mut SlabHeader,
+  free_objects: *mut FreeObject,
+  in_use: usize
+}
+
+struct FreeObject {
+  next: *mut FreeObject
+}
+
+// The slab list is only reached through the cache's lock.
+unsafe impl Send for SlabList {}
+unsafe impl<T> Sync for Slab<T> {}
+
+impl SlabStatistics {
+
+  /// The number of objects that are allocated and not yet freed.
+  pub fn active(&self) -> usize {
+    self.allocations - self.frees
+  }
+
+}
+
+impl<T> Slab<T> {
+
+  /// Creates an empty cache.
+  /// # Arguments
+  /// * 'name' - The name printed by dump.
+  /// * 'constructor' - Creates the initial value of each object as it is allocated.
+  pub const fn new(name: &'static str, constructor: fn() -> T) -> Self {
+    Self {
+      name,
+      constructor,
+      slabs: Mutex::new(SlabList {
+        head: null_mut(),
+        statistics: SlabStatistics {
+          allocations: 0,
+          frees: 0,
+          slabs: 0,
+          objects_per_slab: 0
+        }
+      }),
+      object_type: PhantomData
+    }
+  }
+
+  /// The size of each object, large enough to hold the free list link when the object is free.
+  fn object_size() -> usize {
+    let align = Self::object_align();
+    let size = mem::size_of::<T>().max(mem::size_of::<FreeObject>());
+    (size + align - 1) & !(align - 1)
+  }
+
+  fn object_align() -> usize {
+    mem::align_of::<T>().max(mem::align_of::<FreeObject>())
+  }
+
+  /// The offset of the first object from the start of the slab.
+  fn objects_offset() -> usize {
+    let align = Self::object_align();
+    (mem::size_of::<SlabHeader>() + align - 1) & !(align - 1)
+  }
+
+  /// The page allocator order of each slab.
+  fn slab_order() -> usize {
+    order_for_size(Self::objects_offset() + Self::object_size() * MIN_OBJECTS_PER_SLAB)
+  }
+
+  fn slab_size() -> usize {
+    PAGE_SIZE << Self::slab_order()
+  }
+
+  /// Allocates an object and initializes it with the cache's constructor.
+  /// Returns None if the page allocator is out of memory.
+  pub fn alloc(&self) -> Option<&'static mut T> {
+    let object = {
+      let mut slabs = self.slabs.lock();
+      let mut slab = slabs.head;
+      unsafe {
+        while !slab.is_null() && (*slab).free_objects.is_null() {
+          slab = (*slab).next;
+        }
+      }
+
+      if slab.is_null() {
+        slab = Self::grow(&mut slabs)?;
+      }
+
+      slabs.statistics.allocations += 1;
+      unsafe {
+        let object = (*slab).free_objects;
+        (*slab).free_objects = (*object).next;
+        (*slab).in_use += 1;
+        object as *mut T
+      }
+    };
+
+    unsafe {
+      object.write((self.constructor)());
+      Some(&mut *object)
+    }
+  }
+
+  /// Drops an object and returns it to its slab.
+  /// # Arguments
+  /// * 'object' - An object returned from alloc on this cache.
+  pub unsafe fn dealloc(&self, object: *mut T) {
+    drop_in_place(object);
+
+    let mut slabs = self.slabs.lock();
+    let slab = (object as usize & !(Self::slab_size() - 1)) as *mut SlabHeader;
+
+    let free_object = object as *mut FreeObject;
+    (*free_object).next = (*slab).free_objects;
+    (*slab).free_objects = free_object;
+    (*slab).in_use -= 1;
+    slabs.statistics.frees += 1;
+
+    if (*slab).in_use == 0 && !(slabs.head == slab && (*slab).next.is_null()) {
+      Self::release(&mut slabs, slab);
+    }
+  }
+
+  /// Returns the cache's counters.
+  pub fn statistics(&self) -> SlabStatistics {
+    self.slabs.lock().statistics
+  }
+
+  /// Prints the cache's counters.
+  pub fn print_statistics(&self) {
+    let statistics = self.statistics();
+    println!("{}: {} active, {} allocations, {} frees, {} slabs of {} objects",
+             self.name, statistics.active(), statistics.allocations, statistics.frees,
+             statistics.slabs, statistics.objects_per_slab);
+  }
+
+  /// Gets a new slab from the page allocator and cuts it into free objects.
+  fn grow(slabs: &mut SlabList) -> Option<*mut SlabHeader> {
+    let address = FREE_PAGE_LIST.alloc_pages(Self::slab_order(), PageOwner::Slab)?;
+    let slab = address as *mut SlabHeader;
+
+    let objects_per_slab = (Self::slab_size() - Self::objects_offset()) / Self::object_size();
+    unsafe {
+      slab.write(SlabHeader {
+        next: slabs.head,
+        free_objects: null_mut(),
+        in_use: 0
+      });
+
+      // Link the objects from last to first so that they are handed out in address order.
+      for index in (0..objects_per_slab).rev() {
+        let free_object = (address + Self::objects_offset() + index * Self::object_size()) as *mut FreeObject;
+        (*free_object).next = (*slab).free_objects;
+        (*slab).free_objects = free_object;
+      }
+    }
+
+    slabs.head = slab;
+    slabs.statistics.slabs += 1;
+    slabs.statistics.objects_per_slab = objects_per_slab;
+    Some(slab)
+  }
+
+  /// Unlinks an empty slab and returns it to the page allocator.
+  unsafe fn release(slabs: &mut SlabList, slab: *mut SlabHeader) {
+    if slabs.head == slab {
+      slabs.head = (*slab).next;
+    } else {
+      let mut previous = slabs.head;
+      while (*previous).next != slab {
+        previous = (*previous).next;
+      }
+      (*previous).next = (*slab).next;
+    }
+
+    slabs.statistics.slabs -= 1;
+    FREE_PAGE_LIST.dealloc_pages(slab as usize, Self::slab_order());
+  }
+
+}
+
+/// Prints the counters of every kernel object cache.
+/// Control-P on the console calls it.
+pub fn dump() {
+  println!("Slab caches:");
+  PROCESS_CACHE.print_statistics();
+  FILE_CACHE.print_statistics();
+  INODE_CACHE.print_statistics();
+  PIPE_CACHE.print_statistics();
+}
@@ -0,0 +1,283 @@
+//! # Swap
+//! Swap space for user pages on the IDE disk ide::SWAP_DISK, which is separate from the file system disk.
+//! When the page allocator runs out of pages, reclaim_page picks a victim with the clock (second chance) algorithm.
+//! The clock hand sweeps over the user pages of every registered page directory. A page whose accessed bit is set
+//! gets a second chance: the bit is cleared and the hand moves on. The first page found with the bit clear is written
+//! to a free swap slot, and its page table entry is replaced by a swapped entry that holds the slot number.
+//! A page fault on a swapped entry reads the page back in with swap_in.
+//! Only pages with a single reference are evicted, since there is no reverse map to find every page table that maps
+//! a shared page. A swapped entry copied by fork shares the slot, so slots are reference counted.
+//! An evicted entry is only invalidated in the TLB of the current cpu, so the clock hand skips address spaces that are
+//! loaded on another cpu. Every cpu reports the address space it loads with set_loaded_address_space. User pages are
+//! not global, so a cpu that loads the address space later does so with a TLB that no longer holds the entry.
+
+use core::slice;
+use spin::Mutex;
+use x86::tlb;
+use acpi::MAX_CPUS;
+use ide::{self, SECTOR_SIZE, SWAP_DISK};
+use memory_layout::{KERNEL_BASE, map_physical_virtual, map_virtual_to_physical};
+use mmu::{page_round_down, PAGE_DIRECTORY_INDEX_SHIFT, PAGE_SIZE, PTE_COPY_ON_WRITE, PTE_SWAPPED};
+use page_allocator::{FREE_PAGE_LIST, PageOwner};
+use paging::{EntryBits, NO_EXECUTE_BIT, PD, PTEntry, PTFlags};
+use param::SWAP_SLOT_COUNT;
+use process::get_current_cpu_id;
+use virtual_memory::walk_page_directory;
+
+/// The number of disk sectors in a swap slot.
+const SECTORS_PER_SLOT: usize = PAGE_SIZE / SECTOR_SIZE as usize;
+
+/// The maximum number of page directories the clock hand sweeps over.
+const MAX_ADDRESS_SPACES: usize = 64;
+
+/// The page table entry bits kept in a swapped entry and restored when the page is read back in.
+const SWAPPED_FLAGS: EntryBits = PTFlags::RW.bits() | PTFlags::US.bits() | PTE_COPY_ON_WRITE | NO_EXECUTE_BIT;
+
+struct Swap {
+  /// The number of page table entries that refer to each slot. A slot is free when this is 0.
+  slot_references: [u8; SWAP_SLOT_COUNT],
+  /// The addresses of the page directories that hold evictable user pages, or 0 for an unused entry.
+  address_spaces: [usize; MAX_ADDRESS_SPACES],
+  /// The address of the page directory loaded on each cpu, or 0 while a cpu has no user address space loaded.
+  loaded: [usize; MAX_CPUS],
+  /// The address of the page directory each cpu is writing a page of to swap, or 0. remove_address_space clears it.
+  evicting: [usize; MAX_CPUS],
+  /// The clock hand: an index into address_spaces and a user address in that page directory.
+  hand_space: usize,
+  hand_address: usize
+}
+
+/// A page the clock hand picked for eviction.
+struct Victim {
+  /// The address of the page directory that maps the page.
+  address_space: usize,
+  /// The user address the page is mapped at.
+  address: usize,
+  page: usize,
+  /// The page table entry when the page was picked. Its accessed bit is clear.
+  entry: EntryBits
+}
+
+static SWAP: Mutex<Swap> = Mutex::new(Swap {
+  slot_references: [0; SWAP_SLOT_COUNT],
+  address_spaces: [0; MAX_ADDRESS_SPACES],
+  loaded: [0; MAX_CPUS],
+  evicting: [0; MAX_CPUS],
+  hand_space: 0,
+  hand_address: 0
+});
+
+impl Swap {
+
+  fn free_slot(&self) -> Option<usize> {
+    self.slot_references.iter().position(|&references| references == 0)
+  }
+
+  /// Returns true if the page directory at address_space is loaded on a cpu other than cpu.
+  fn is_loaded_elsewhere(&self, address_space: usize, cpu: usize) -> bool {
+    self.loaded.iter().enumerate().any(|(other, &loaded)| other != cpu && loaded == address_space)
+  }
+
+  /// Sweeps the clock hand to the next page that can be evicted, skipping address spaces loaded on other cpus.
+  /// The first full sweep may only clear accessed bits, so the hand may need to pass every page twice.
+  /// Returns None if there is no such page.
+  fn pick_victim(&mut self, cpu: usize) -> Option<Victim> {
+    let mut wraps = 0;
+    while wraps < 3 {
+      let address_space = self.address_spaces[self.hand_space];
+      if address_space == 0 || self.hand_address >= KERNEL_BASE || self.is_loaded_elsewhere(address_space, cpu) {
+        if self.advance_space() {
+          wraps += 1;
+        }
+        continue;
+      }
+
+      let address = self.hand_address;
+      let page_directory = unsafe { &mut *(address_space as *mut PD) };
+      let page_table_entry = match walk_page_directory(page_directory, address, false) {
+        Some(page_table_entry) => page_table_entry,
+        None => {
+          // There is no page table here, so skip to the next page directory entry.
+          self.hand_address = ((address >> PAGE_DIRECTORY_INDEX_SHIFT) + 1) << PAGE_DIRECTORY_INDEX_SHIFT;
+          continue;
+        }
+      };
+      self.hand_address += PAGE_SIZE;
+
+      if !page_table_entry.is_present() || !page_table_entry.is_user_mode_allowed() {
+        continue;
+      }
+
+      let page = map_physical_virtual(page_table_entry.address().as_usize());
+      if FREE_PAGE_LIST.reference_count(page) != 1 {
+        continue;
+      }
+
+      if page_table_entry.is_accessed() {
+        // Second chance.
+        page_table_entry.0 &= !PTFlags::A.bits();
+        unsafe {
+          tlb::flush(address);
+        }
+        continue;
+      }
+
+      return Some(Victim { address_space, address, page, entry: page_table_entry.0 });
+    }
+    None
+  }
+
+  /// Moves the clock hand to the next address space.
+  /// Returns true if the hand wrapped around to the first address space.
+  fn advance_space(&mut self) -> bool {
+    self.hand_address = 0;
+    self.hand_space += 1;
+    if self.hand_space == MAX_ADDRESS_SPACES {
+      self.hand_space = 0;
+      return true;
+    }
+    false
+  }
+
+}
+
+/// Returns true if a page table entry refers to a swap slot.
+pub fn is_swapped(page_table_entry: &PTEntry) -> bool {
+  !page_table_entry.is_present() && page_table_entry.0 & PTE_SWAPPED != 0
+}
+
+fn slot(page_table_entry: &PTEntry) -> usize {
+  page_table_entry.address().as_usize() / PAGE_SIZE
+}
+
+/// Adds a reference to the slot of a swapped entry that is being copied into another page directory.
+pub fn share_slot(page_table_entry: &PTEntry) {
+  SWAP.lock().slot_references[slot(page_table_entry)] += 1;
+}
+
+/// Drops a reference to the slot of a swapped entry that is being unmapped.
+pub fn release_slot(page_table_entry: &PTEntry) {
+  SWAP.lock().slot_references[slot(page_table_entry)] -= 1;
+}
+
+/// Lets the clock hand evict pages from a page directory.
+/// Returns false if MAX_ADDRESS_SPACES page directories are registered already.
+pub fn add_address_space(page_directory: *mut PD) -> bool {
+  let mut swap = SWAP.lock();
+  match swap.address_spaces.iter().position(|&address_space| address_space == 0) {
+    Some(index) => {
+      swap.address_spaces[index] = page_directory as usize;
+      true
+    }
+    None => false
+  }
+}
+
+/// Records that the current cpu is about to load page_directory, or no user address space if it is null.
+/// Must be called with interrupts disabled, before cr3 is loaded. Holding the lock keeps reclaim_page from evicting a
+/// page of the address space between its check and the load.
+pub fn set_loaded_address_space(page_directory: *const PD) {
+  let cpu = get_current_cpu_id() as usize;
+  SWAP.lock().loaded[cpu] = page_directory as usize;
+}
+
+/// Stops the clock hand from visiting a page directory that is about to be freed, and keeps a page of it that is
+/// being written to swap from being evicted.
+pub fn remove_address_space(page_directory: *mut PD) {
+  let mut swap = SWAP.lock();
+  let swap = &mut *swap;
+  for address_space in swap.address_spaces.iter_mut().chain(swap.evicting.iter_mut()) {
+    if *address_space == page_directory as usize {
+      *address_space = 0;
+    }
+  }
+}
+
+/// Evicts a user page to swap.
+/// The victim is picked with the swap lock held, but the lock is dropped while the page is written, so that other cpus
+/// do not spin for the whole disk write. The page keeps an extra reference meanwhile, so that it is not freed and
+/// reused. Once the write is done, the page is only evicted if its page directory was not removed, no cpu loaded it
+/// and its entry was not changed or accessed in the meantime. Otherwise the slot is released and the hand moves on.
+/// Returns the evicted page, which keeps its single reference and now belongs to the caller.
+/// Returns None if there is no swap disk, no free slot, no page that can be evicted or the disk write fails.
+pub fn reclaim_page() -> Option<usize> {
+  if !ide::has_disk(SWAP_DISK) {
+    return None;
+  }
+
+  let cpu = get_current_cpu_id() as usize;
+  loop {
+    let (slot, victim) = {
+      let mut swap = SWAP.lock();
+      let slot = swap.free_slot()?;
+      let victim = swap.pick_victim(cpu)?;
+      swap.slot_references[slot] = 1;
+      swap.evicting[cpu] = victim.address_space;
+      FREE_PAGE_LIST.add_reference(victim.page);
+      (slot, victim)
+    };
+
+    let contents = unsafe { slice::from_raw_parts(victim.page as *const u8, PAGE_SIZE) };
+    let written = ide::write_sectors(SWAP_DISK, (slot * SECTORS_PER_SLOT) as u32, contents);
+
+    let mut swap = SWAP.lock();
+    let unchanged = swap.evicting[cpu] == victim.address_space && !swap.is_loaded_elsewhere(victim.address_space, cpu);
+    swap.evicting[cpu] = 0;
+    if written && unchanged {
+      let page_directory = unsafe { &mut *(victim.address_space as *mut PD) };
+      if let Some(page_table_entry) = walk_page_directory(page_directory, victim.address, false) {
+        if page_table_entry.0 == victim.entry && FREE_PAGE_LIST.reference_count(victim.page) == 2 {
+          *page_table_entry = PTEntry(((slot as EntryBits) << PAGE_SIZE.trailing_zeros()) | PTE_SWAPPED | (victim.entry & SWAPPED_FLAGS));
+          unsafe {
+            tlb::flush(victim.address);
+            FREE_PAGE_LIST.remove_reference(victim.page);
+          }
+          return Some(victim.page);
+        }
+      }
+    }
+
+    swap.slot_references[slot] = 0;
+    drop(swap);
+    // The page is freed here if it was unmapped during the write.
+    unsafe {
+      FREE_PAGE_LIST.remove_reference(victim.page);
+    }
+    if !written {
+      return None;
+    }
+  }
+}
+
+/// Reads a swapped out page back in after a page fault on its entry.
+/// Returns false if the entry is not swapped, or if there is no memory or the disk read fails.
+pub fn swap_in(page_directory: &mut PD, virtual_address: usize) -> bool {
+  let address = page_round_down(virtual_address);
+  let page_table_entry = match walk_page_directory(page_directory, address, false) {
+    Some(page_table_entry) if is_swapped(page_table_entry) => *page_table_entry,
+    _ => {
+      return false;
+    }
+  };
+
+  let page = match FREE_PAGE_LIST.alloc_page(PageOwner::UserPage) {
+    Some(page) => page,
+    None => {
+      return false;
+    }
+  };
+
+  let contents = unsafe { slice::from_raw_parts_mut(page as *mut u8, PAGE_SIZE) };
+  if !ide::read_sectors(SWAP_DISK, (slot(&page_table_entry) * SECTORS_PER_SLOT) as u32, contents) {
+    unsafe {
+      FREE_PAGE_LIST.dealloc_page(page);
+    }
+    return false;
+  }
+  release_slot(&page_table_entry);
+
+  // Allocating the page may have evicted other pages, so walk the page directory again.
+  let page_table_entry = walk_page_directory(page_directory, address, false).unwrap();
+  *page_table_entry = PTEntry(map_virtual_to_physical(page) as EntryBits | PTFlags::P.bits() | (page_table_entry.0 & SWAPPED_FLAGS));
+  true
+}
@@ -1,3 +1,124 @@
-extern "C" {
-    pub fn argint(n: i32, ip: *mut i32) -> i32;
+//! # System Calls
+//! User code makes a system call with int T_SYSCALL. The system call number is in eax and the arguments are on
+//! the user stack, above the return address of the library stub. The result is returned in eax.
+
+use file::File;
+use param::NOFILE;
+use process::my_process;
+use sysfile::{sys_close, sys_dup, sys_open, sys_read, sys_write};
+use sysproc::{sys_exec, sys_exit, sys_fork, sys_getpid, sys_getpriority, sys_kill, sys_mmap, sys_mprotect, sys_munmap,
+              sys_sbrk, sys_setpriority, sys_settickets, sys_shmat, sys_shmctl, sys_shmdt, sys_shmget, sys_sleep,
+              sys_uptime, sys_wait};
+use user_memory::{self, UserData, UserPtr, UserSlice};
+
+// System call numbers.
+pub const SYS_FORK: u32 = 1;
+pub const SYS_EXIT: u32 = 2;
+pub const SYS_WAIT: u32 = 3;
+pub const SYS_READ: u32 = 5;
+pub const SYS_KILL: u32 = 6;
+pub const SYS_EXEC: u32 = 7;
+pub const SYS_DUP: u32 = 10;
+pub const SYS_GETPID: u32 = 11;
+pub const SYS_SBRK: u32 = 12;
+pub const SYS_SLEEP: u32 = 13;
+pub const SYS_UPTIME: u32 = 14;
+pub const SYS_OPEN: u32 = 15;
+pub const SYS_WRITE: u32 = 16;
+pub const SYS_CLOSE: u32 = 21;
+pub const SYS_MMAP: u32 = 22;
+pub const SYS_MUNMAP: u32 = 23;
+pub const SYS_MPROTECT: u32 = 24;
+pub const SYS_SHMGET: u32 = 25;
+pub const SYS_SHMAT: u32 = 26;
+pub const SYS_SHMDT: u32 = 27;
+pub const SYS_SHMCTL: u32 = 28;
+pub const SYS_SETPRIORITY: u32 = 29;
+pub const SYS_GETPRIORITY: u32 = 30;
+pub const SYS_SETTICKETS: u32 = 31;
+
+/// Fetches the 32-bit int at address in the current process's memory.
+/// Returns None if the process may not read it.
+pub fn fetch_int(address: usize) -> Option<i32> {
+    UserPtr::<i32>::new(address).read()
+}
+
+/// Fetches the nth 32-bit system call argument.
+pub fn argint(n: usize) -> Option<i32> {
+    let process = my_process()?;
+    let stack_pointer = unsafe { (*process.trap_frame).esp } as usize;
+    fetch_int(stack_pointer + 4 + 4 * n)
+}
+
+/// Fetches the nth system call argument as a pointer to a block of size bytes.
+/// Returns None if the block is not below KERNEL_BASE.
+pub fn argptr(n: usize, size: usize) -> Option<UserSlice> {
+    UserSlice::new(argint(n)? as usize, size)
+}
+
+/// Fetches the nth system call argument as a pointer to a T.
+pub fn arguser<T: UserData>(n: usize) -> Option<UserPtr<T>> {
+    Some(UserPtr::new(argint(n)? as usize))
+}
+
+/// Fetches the nth system call argument as a null terminated string, copied into buffer.
+/// Returns None if the string can not be read or does not fit into buffer with its terminator.
+pub fn argstr(n: usize, buffer: &mut [u8]) -> Option<&[u8]> {
+    user_memory::fetch_str(argint(n)? as usize, buffer)
+}
+
+/// Fetches the nth system call argument as a file descriptor and returns the open file.
+/// Returns None if the descriptor is not open.
+pub fn argfd(n: usize) -> Option<&'static mut File> {
+    let fd = argint(n)?;
+    if fd < 0 || fd as usize >= NOFILE {
+        return None;
+    }
+
+    // Open files come from the file cache and stay allocated while the descriptor refers to them.
+    unsafe { my_process()?.open_files[fd as usize].as_mut() }
+}
+
+/// Runs the system call requested by the current process.
+pub fn syscall() {
+    let process = match my_process() {
+        Some(process) => process,
+        None => {
+            panic!("syscall: no process");
+        }
+    };
+
+    let trap_frame = unsafe { &mut *process.trap_frame };
+    let number = trap_frame.eax;
+    let result = match number {
+        SYS_FORK => sys_fork(),
+        SYS_EXIT => sys_exit(),
+        SYS_WAIT => sys_wait(),
+        SYS_READ => sys_read(),
+        SYS_KILL => sys_kill(),
+        SYS_EXEC => sys_exec(),
+        SYS_DUP => sys_dup(),
+        SYS_GETPID => sys_getpid(),
+        SYS_SBRK => sys_sbrk(),
+        SYS_SLEEP => sys_sleep(),
+        SYS_UPTIME => sys_uptime(),
+        SYS_OPEN => sys_open(),
+        SYS_WRITE => sys_write(),
+        SYS_CLOSE => sys_close(),
+        SYS_MMAP => sys_mmap(),
+        SYS_MUNMAP => sys_munmap(),
+        SYS_MPROTECT => sys_mprotect(),
+        SYS_SHMGET => sys_shmget(),
+        SYS_SHMAT => sys_shmat(),
+        SYS_SHMDT => sys_shmdt(),
+        SYS_SHMCTL => sys_shmctl(),
+        SYS_SETPRIORITY => sys_setpriority(),
+        SYS_GETPRIORITY => sys_getpriority(),
+        SYS_SETTICKETS => sys_settickets(),
+        _ => {
+            println!("pid {}: unknown sys call {}", process.id, number);
+            -1
+        }
+    };
+    trap_frame.eax = result as u32;
 }
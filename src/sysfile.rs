@@ -0,0 +1,184 @@
+//! # File System Calls
+//! open, read, write, close and dup on the files of the current process.
+//! Files can only be opened, not created, since the file system is read from the disk that mkfs built.
+
+use core::cmp;
+use file::File;
+use fs::{self, BSIZE};
+use param::NOFILE;
+use process::my_process;
+use syscall::{argfd, argint, argptr, argstr};
+use user_memory::{copy_from_user, copy_to_user};
+
+// Open modes.
+pub const O_RDONLY: i32 = 0x000;
+pub const O_WRONLY: i32 = 0x001;
+pub const O_RDWR: i32 = 0x002;
+pub const O_CREATE: i32 = 0x200;
+
+/// Gives file the lowest free file descriptor of the current process, like fdalloc.
+/// Returns the descriptor, or None if the process has none free.
+fn fd_alloc(file: *mut File) -> Option<usize> {
+    let process = my_process()?;
+    let fd = process.open_files.iter().position(|open_file| open_file.is_null())?;
+    process.open_files[fd] = file;
+    Some(fd)
+}
+
+/// open(path, mode)
+pub fn sys_open() -> i32 {
+    let mut path_buffer = [0u8; 128];
+    let (path, mode) = match (argstr(0, &mut path_buffer), argint(1)) {
+        (Some(path), Some(mode)) if mode & O_CREATE == 0 => (path, mode),
+        _ => {
+            return -1;
+        }
+    };
+
+    let inode = match fs::namei(path) {
+        Some(inode) => inode,
+        None => {
+            return -1;
+        }
+    };
+    let readable = mode & O_WRONLY == 0;
+    let writable = mode & (O_WRONLY | O_RDWR) != 0;
+    if inode.is_directory() && writable {
+        inode.put();
+        return -1;
+    }
+
+    let file = match File::open(inode, readable, writable) {
+        Some(file) => file,
+        None => {
+            return -1;
+        }
+    };
+    match fd_alloc(file) {
+        Some(fd) => fd as i32,
+        None => {
+            file.close();
+            -1
+        }
+    }
+}
+
+/// read(fd, buffer, n)
+pub fn sys_read() -> i32 {
+    let (file, n) = match (argfd(0), argint(2)) {
+        (Some(file), Some(n)) if n >= 0 => (file, n as usize),
+        _ => {
+            return -1;
+        }
+    };
+    let buffer = match argptr(1, n) {
+        Some(buffer) => buffer,
+        None => {
+            return -1;
+        }
+    };
+
+    // The data is read before it is copied out, so a failed copy moves the offset back to where the read started.
+    let offset = file.offset();
+    let mut block = [0u8; BSIZE];
+    let mut done = 0;
+    while done < n {
+        let chunk = cmp::min(n - done, BSIZE);
+        let count = match file.read(&mut block[..chunk]) {
+            Some(count) => count,
+            None => {
+                file.set_offset(offset);
+                return -1;
+            }
+        };
+        if !copy_to_user(buffer.address() + done, &block[..count]) {
+            file.set_offset(offset);
+            return -1;
+        }
+        done += count;
+        if count < chunk {
+            break;
+        }
+    }
+    done as i32
+}
+
+/// write(fd, buffer, n)
+pub fn sys_write() -> i32 {
+    let (file, n) = match (argfd(0), argint(2)) {
+        (Some(file), Some(n)) if n >= 0 => (file, n as usize),
+        _ => {
+            return -1;
+        }
+    };
+    let buffer = match argptr(1, n) {
+        Some(buffer) => buffer,
+        None => {
+            return -1;
+        }
+    };
+
+    let mut block = [0u8; BSIZE];
+    let mut done = 0;
+    while done < n {
+        let chunk = cmp::min(n - done, BSIZE);
+        if !copy_from_user(&mut block[..chunk], buffer.address() + done) {
+            return -1;
+        }
+        let count = match file.write(&block[..chunk]) {
+            Some(count) => count,
+            None => {
+                return -1;
+            }
+        };
+        done += count;
+        if count < chunk {
+            break;
+        }
+    }
+    done as i32
+}
+
+/// close(fd)
+pub fn sys_close() -> i32 {
+    let fd = match argint(0) {
+        Some(fd) if fd >= 0 && (fd as usize) < NOFILE => fd as usize,
+        _ => {
+            return -1;
+        }
+    };
+    let process = match my_process() {
+        Some(process) => process,
+        None => {
+            return -1;
+        }
+    };
+
+    let file = process.open_files[fd];
+    if file.is_null() {
+        return -1;
+    }
+    process.open_files[fd] = core::ptr::null_mut();
+    unsafe {
+        (*file).close();
+    }
+    0
+}
+
+/// dup(fd)
+pub fn sys_dup() -> i32 {
+    let file = match argfd(0) {
+        Some(file) => file,
+        None => {
+            return -1;
+        }
+    };
+    file.dup();
+    match fd_alloc(file) {
+        Some(fd) => fd as i32,
+        None => {
+            file.close();
+            -1
+        }
+    }
+}
@@ -1,88 +1,362 @@
-/*use syscall::argint;
-use process::{growproc, exit, kill, fork, sleep, wait, myproc};
-use trap::ticks;
-use x86::io::outw;
-
+use alloc::vec;
+use alloc::vec::Vec;
 use core::ffi::c_void;
+use core::sync::atomic::{AtomicU32, Ordering};
+use exec::{exec, MAX_ARGUMENTS};
+use mmu::PAGE_SIZE;
+use process::{self, exit, fork, grow_process, kill, my_process, PROCESS_TABLE, sleep, wait};
+use shm::{self, IPC_STAT, SharedMemoryStatus};
+use syscall::{argfd, argint, argstr, arguser, fetch_int};
+use user_memory::fetch_str;
+use trap::TICKS;
+use user_memory::UserPtr;
+use vma::{self, MAP_ANONYMOUS};
+
+pub fn sys_sbrk() -> i32 {
+    let n = match argint(0) {
+        Some(n) => n,
+        None => {
+            return -1;
+        }
+    };
 
-#[no_mangle]
-pub unsafe extern "C" fn sys_fork() -> i32 {
-    fork()
+    match grow_process(n as isize) {
+        Some(address) => address as i32,
+        None => -1
+    }
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn sys_exit() -> i32 {
-    exit();
-    0
+/// mmap(address, length, protection, flags, fd, offset)
+pub fn sys_mmap() -> i32 {
+    let (address, length, protection, flags, offset) = match (argint(0), argint(1), argint(2), argint(3), argint(5)) {
+        (Some(address), Some(length), Some(protection), Some(flags), Some(offset)) if length > 0 && offset >= 0 => {
+            (address as usize, length as usize, protection as u32, flags as u32, offset as usize)
+        }
+        _ => {
+            return -1;
+        }
+    };
+
+    let file = if flags & MAP_ANONYMOUS != 0 {
+        None
+    } else {
+        match argfd(4) {
+            Some(file) => Some(file),
+            None => {
+                return -1;
+            }
+        }
+    };
+
+    let process = match my_process() {
+        Some(process) => process,
+        None => {
+            return -1;
+        }
+    };
+
+    match vma::mmap(process, address, length, protection, flags, file, offset) {
+        Some(address) => address as i32,
+        None => -1
+    }
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn sys_kill() -> i32 {
-    let mut pid: i32 = 0;
-    
-    if argint(0, &mut pid as *mut i32) < 0 {
+/// munmap(address, length)
+pub fn sys_munmap() -> i32 {
+    let (address, length) = match (argint(0), argint(1)) {
+        (Some(address), Some(length)) => (address as usize, length as usize),
+        _ => {
+            return -1;
+        }
+    };
+
+    let process = match my_process() {
+        Some(process) => process,
+        None => {
+            return -1;
+        }
+    };
+
+    if vma::munmap(process, address, length) {
+        0
+    } else {
         -1
+    }
+}
+
+/// mprotect(address, length, protection)
+pub fn sys_mprotect() -> i32 {
+    let (address, length, protection) = match (argint(0), argint(1), argint(2)) {
+        (Some(address), Some(length), Some(protection)) => (address as usize, length as usize, protection as u32),
+        _ => {
+            return -1;
+        }
+    };
+
+    let process = match my_process() {
+        Some(process) => process,
+        None => {
+            return -1;
+        }
+    };
+
+    if vma::mprotect(process, address, length, protection) {
+        0
     } else {
-        kill(pid)
+        -1
     }
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn sys_sbrk() -> i32 {
-    let mut n: i32 = 0;
+/// shmget(key, size, flags)
+pub fn sys_shmget() -> i32 {
+    let (key, size, flags) = match (argint(0), argint(1), argint(2)) {
+        (Some(key), Some(size), Some(flags)) if size >= 0 => (key, size as usize, flags),
+        _ => {
+            return -1;
+        }
+    };
 
-    if argint(0, &mut n as *mut i32) < 0 {
-        return -1;
+    match shm::get(key, size, flags) {
+        Some(id) => id as i32,
+        None => -1
     }
+}
 
-    let addr = (*myproc()).sz;
-    if growproc(n) < 0 {
-        return -1;
+/// shmat(id, address, flags)
+pub fn sys_shmat() -> i32 {
+    let (id, address, flags) = match (argint(0), argint(1), argint(2)) {
+        (Some(id), Some(address), Some(flags)) if id >= 0 => (id as usize, address as usize, flags),
+        _ => {
+            return -1;
+        }
+    };
+
+    let process = match my_process() {
+        Some(process) => process,
+        None => {
+            return -1;
+        }
+    };
+
+    match shm::attach(process, id, address, flags) {
+        Some(address) => address as i32,
+        None => -1
     }
+}
+
+/// shmdt(address)
+pub fn sys_shmdt() -> i32 {
+    let address = match argint(0) {
+        Some(address) => address as usize,
+        None => {
+            return -1;
+        }
+    };
 
-    addr as i32
+    let process = match my_process() {
+        Some(process) => process,
+        None => {
+            return -1;
+        }
+    };
+
+    if shm::detach(process, address) {
+        0
+    } else {
+        -1
+    }
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn sys_sleep() -> i32 {
-    let mut n: i32 = 0;
+/// shmctl(id, command, status)
+pub fn sys_shmctl() -> i32 {
+    let (id, command) = match (argint(0), argint(1)) {
+        (Some(id), Some(command)) if id >= 0 => (id as usize, command),
+        _ => {
+            return -1;
+        }
+    };
 
-    if argint(0, &mut n as *mut i32) < 0 {
+    let mut status = SharedMemoryStatus { key: 0, size: 0, attaches: 0 };
+    if !shm::control(id, command, &mut status) {
         return -1;
     }
 
-    let ticks0 = ticks;
+    if command == IPC_STAT {
+        match arguser::<SharedMemoryStatus>(2) {
+            Some(pointer) if pointer.write(&status) => {}
+            _ => {
+                return -1;
+            }
+        }
+    }
+    0
+}
+
+pub fn sys_fork() -> i32 {
+    match fork() {
+        Some(id) => id as i32,
+        None => -1
+    }
+}
+
+/// exit(status)
+pub fn sys_exit() -> i32 {
+    let status = argint(0).unwrap_or(0);
+    exit(status);
+}
 
-    while ((ticks - ticks0) as i32) < n {
-        if (*myproc()).killed != 0 {
+/// wait(status), where status is the address the child's exit status is written to, or 0.
+pub fn sys_wait() -> i32 {
+    let status_address = match argint(0) {
+        Some(address) => address as usize,
+        None => {
             return -1;
         }
+    };
 
-        sleep(&ticks as *const u32 as *const c_void, core::ptr::null());
+    let (id, status) = match wait() {
+        Some(child) => child,
+        None => {
+            return -1;
+        }
+    };
+    if status_address != 0 && !UserPtr::<i32>::new(status_address).write(&status) {
+        return -1;
     }
+    id as i32
+}
 
-    0
+/// exec(path, argv, envp), where argv and envp are null terminated arrays of string pointers. envp may be 0.
+pub fn sys_exec() -> i32 {
+    let mut path_buffer = [0u8; 128];
+    let path = match argstr(0, &mut path_buffer) {
+        Some(path) => path,
+        None => {
+            return -1;
+        }
+    };
+    let (argv, envp) = match (argint(1), argint(2)) {
+        (Some(argv), Some(envp)) => match (fetch_strings(argv as usize), fetch_strings(envp as usize)) {
+            (Some(argv), Some(envp)) => (argv, envp),
+            _ => {
+                return -1;
+            }
+        },
+        _ => {
+            return -1;
+        }
+    };
+
+    let argv: Vec<&[u8]> = argv.iter().map(|argument| argument.as_slice()).collect();
+    let envp: Vec<&[u8]> = envp.iter().map(|variable| variable.as_slice()).collect();
+    if exec(path, &argv, &envp) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Copies the strings of the null terminated array of string pointers at address in user memory.
+/// An address of 0 is an empty array. Returns None if the array or a string can not be read, or if it has more than
+/// MAX_ARGUMENTS strings.
+fn fetch_strings(address: usize) -> Option<Vec<Vec<u8>>> {
+    let mut strings = Vec::new();
+    if address == 0 {
+        return Some(strings);
+    }
+
+    loop {
+        let string_address = fetch_int(address.checked_add(4 * strings.len())?)? as usize;
+        if string_address == 0 {
+            return Some(strings);
+        }
+        if strings.len() == MAX_ARGUMENTS {
+            return None;
+        }
+
+        let mut buffer = vec![0u8; PAGE_SIZE];
+        let length = fetch_str(string_address, &mut buffer)?.len();
+        buffer.truncate(length);
+        strings.push(buffer);
+    }
+}
+
+/// kill(pid)
+pub fn sys_kill() -> i32 {
+    match argint(0) {
+        Some(id) if id > 0 && kill(id as usize) => 0,
+        _ => -1
+    }
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn sys_wait() -> i32 {
-    wait()
+pub fn sys_getpid() -> i32 {
+    match my_process() {
+        Some(process) => process.id as i32,
+        None => -1
+    }
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn sys_getpid() -> i32 {
-    (*myproc()).pid as i32
+/// setpriority(pid, priority), where a pid of 0 is the calling process.
+/// The priorities depend on the scheduling policy. With mlfq, a priority is a queue, 0 being the highest.
+pub fn sys_setpriority() -> i32 {
+    match (argint(0).and_then(process_id), argint(1)) {
+        (Some(id), Some(priority)) if process::set_priority(id, priority) => 0,
+        _ => -1
+    }
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn sys_uptime() -> i32 {
-    let xticks = ticks as i32;
-    return xticks;
+/// getpriority(pid), where a pid of 0 is the calling process.
+pub fn sys_getpriority() -> i32 {
+    match argint(0).and_then(process_id).and_then(process::priority) {
+        Some(priority) => priority,
+        None => -1
+    }
+}
+
+/// settickets(n), which sets the tickets of the calling process for the stride and lottery scheduling policies.
+pub fn sys_settickets() -> i32 {
+    match (argint(0), process_id(0)) {
+        (Some(tickets), Some(id)) if process::set_tickets(id, tickets) => 0,
+        _ => -1
+    }
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn sys_halt() -> i32 {
-    outw(0x604, 0x0 | 0x2000);
+/// Returns the process id a pid argument names. A pid of 0 names the calling process.
+fn process_id(pid: i32) -> Option<usize> {
+    match pid {
+        0 => Some(my_process()?.id),
+        pid if pid > 0 => Some(pid as usize),
+        _ => None
+    }
+}
+
+/// sleep(n), which sleeps for n clock ticks.
+pub fn sys_sleep() -> i32 {
+    let n = match argint(0) {
+        Some(n) if n >= 0 => n as u32,
+        _ => {
+            return -1;
+        }
+    };
+    let process = match my_process() {
+        Some(process) => process,
+        None => {
+            return -1;
+        }
+    };
+
+    let ticks0 = TICKS.load(Ordering::Relaxed);
+    let mut process_table = PROCESS_TABLE.lock();
+    while TICKS.load(Ordering::Relaxed).wrapping_sub(ticks0) < n {
+        if process.killed {
+            return -1;
+        }
+        process_table = sleep(&TICKS as *const AtomicU32 as *const c_void, process_table);
+    }
     0
 }
-*/
\ No newline at end of file
+
+/// Returns how many clock ticks have happened since boot.
+pub fn sys_uptime() -> i32 {
+    TICKS.load(Ordering::Relaxed) as i32
+}
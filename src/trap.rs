@@ -1,3 +1,169 @@
+//! # Traps
+//! Entry through int T_SYSCALL, the local interrupt controller's timer and spurious interrupts, and the keyboard and
+//! serial port interrupts.
+//! The vectors push a zero error code and the trap number, and alltraps pushes the rest of the TrapFrame before
+//! calling trap. trapret pops the frame and returns to where the trap happened. It is also where a new process
+//! starts running.
+
+use core::arch::global_asm;
+use core::ffi::c_void;
+use core::sync::atomic::{AtomicU32, Ordering};
+use arch::TrapFrame;
+use console;
+use kbd;
+use local_interrupt_controller;
+use process::{self, exit, get_current_cpu_id, my_process, wakeup, yield_cpu};
+use syscall;
+use traps::{IRQ_COM1, IRQ_KBD, IRQ_SPURIOUS, IRQ_TIMER, T_IRQ0, T_SYSCALL};
+
+/// The number of timer interrupts since boot, counted by the first cpu.
+pub static TICKS: AtomicU32 = AtomicU32::new(0);
+
 extern "C" {
-    pub static ticks: u32;
+    /// The entry point for int T_SYSCALL.
+    pub fn syscall_vector();
+    /// The entry point for the local interrupt controller's timer interrupt.
+    pub fn timer_vector();
+    /// The entry point for the local interrupt controller's spurious interrupt.
+    pub fn spurious_vector();
+    /// The entry point for the keyboard interrupt.
+    pub fn keyboard_vector();
+    /// The entry point for the serial port interrupt.
+    pub fn uart_vector();
+    /// Returns to user mode by popping the TrapFrame on top of the stack.
+    pub fn trapret();
+}
+
+// The data segment selector is SEGMENT_KERNEL_DATA << 3. The trap numbers are T_SYSCALL, T_IRQ0 + IRQ_TIMER,
+// T_IRQ0 + IRQ_SPURIOUS, T_IRQ0 + IRQ_KBD and T_IRQ0 + IRQ_COM1.
+global_asm!(r#"
+.globl alltraps
+alltraps:
+  # Build the trap frame.
+  pushl %ds
+  pushl %es
+  pushl %fs
+  pushl %gs
+  pushal
+
+  # Set up the kernel data segments.
+  movw $(2 << 3), %ax
+  movw %ax, %ds
+  movw %ax, %es
+
+  # User code can set the alignment check flag, which would let the kernel access user pages with SMAP enabled.
+  pushfl
+  andl $~(1 << 18), (%esp)
+  popfl
+
+  # Call trap(trap_frame), where trap_frame = %esp.
+  pushl %esp
+  call trap
+  addl $4, %esp
+
+  # Fall through to trapret.
+.globl trapret
+trapret:
+  popal
+  popl %gs
+  popl %fs
+  popl %es
+  popl %ds
+  # Skip the trap number and error code.
+  addl $0x8, %esp
+  iret
+
+.globl syscall_vector
+syscall_vector:
+  pushl $0
+  pushl $64
+  jmp alltraps
+
+.globl timer_vector
+timer_vector:
+  pushl $0
+  pushl $32
+  jmp alltraps
+
+.globl spurious_vector
+spurious_vector:
+  pushl $0
+  pushl $63
+  jmp alltraps
+
+.globl keyboard_vector
+keyboard_vector:
+  pushl $0
+  pushl $33
+  jmp alltraps
+
+.globl uart_vector
+uart_vector:
+  pushl $0
+  pushl $36
+  jmp alltraps
+"#, options(att_syntax));
+
+/// Called from alltraps with the TrapFrame it built on the kernel stack.
+#[no_mangle]
+pub extern "C" fn trap(trap_frame: &mut TrapFrame) {
+    if trap_frame.trapno == T_SYSCALL {
+        if let Some(process) = my_process() {
+            if process.killed {
+                exit(-1);
+            }
+            process.trap_frame = trap_frame;
+        }
+        syscall::syscall();
+        exit_if_killed();
+        return;
+    }
+
+    if trap_frame.trapno == T_IRQ0 + IRQ_TIMER {
+        if get_current_cpu_id() == 0 {
+            let ticks = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+            wakeup(&TICKS as *const AtomicU32 as *const c_void);
+            process::clock(ticks);
+        }
+        local_interrupt_controller::end_of_interrupt();
+
+        // Give up the cpu when the scheduling policy says so, but only when the process was interrupted in user
+        // mode. A tick in kernel mode can only interrupt a cpu's scheduler, which runs no process.
+        if trap_frame.cs & 3 == 3 && my_process().is_some() {
+            exit_if_killed();
+            if process::tick() {
+                yield_cpu();
+            }
+            exit_if_killed();
+        }
+        return;
+    }
+
+    if trap_frame.trapno == T_IRQ0 + IRQ_SPURIOUS {
+        return;
+    }
+
+    if trap_frame.trapno == T_IRQ0 + IRQ_KBD {
+        kbd::keyboard_interrupt();
+        local_interrupt_controller::end_of_interrupt();
+        return;
+    }
+
+    if trap_frame.trapno == T_IRQ0 + IRQ_COM1 {
+        console::uart_interrupt();
+        local_interrupt_controller::end_of_interrupt();
+        return;
+    }
+
+    println!("unexpected trap {} from eip {:#x}", trap_frame.trapno, trap_frame.eip);
+}
+
+/// Exits the current process if it has been killed. Only called on the way back to user mode, where the process
+/// holds no locks.
+fn exit_if_killed() {
+    if let Some(process) = my_process() {
+        if process.killed {
+            exit(-1);
+        }
+    }
 }
@@ -1,8 +1,12 @@
 pub const IRQ_COM1: u32 = 4;
 
+// System call vector.
+pub const T_SYSCALL: u32 = 64;
+
 // IRQ 0 corresponds to int T_IRQ
 pub const T_IRQ0: u32 = 32;
+pub const IRQ_KBD: u32 = 1;
 pub const IRQ_IDE: u32 = 14;
 pub const IRQ_SPURIOUS: u32 = 31;
 pub const IRQ_TIMER: u32 = 0;
-pub const IRQ_ERROR: u32 = 19;
\ No newline at end of file
+pub const IRQ_ERROR: u32 = 19;
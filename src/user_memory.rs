@@ -0,0 +1,240 @@
+//! # User Memory
+//! Checked access to the current process's memory for system calls.
+//! Addresses passed in by user code can not be trusted, so the kernel never dereferences them. Each access goes
+//! through the process's address space one page at a time. A page that is not present or not writable yet is
+//! resolved the way the page fault handler would resolve a user access to it, which brings in lazy heap and stack
+//! pages, swapped pages, mmap areas and copy-on-write copies. The page is then copied through the kernel's mapping
+//! of physical memory. An address the process may not access makes the copy fail instead of faulting the kernel.
+//! UserPtr and UserSlice wrap user addresses so that they can only be used through these copies.
+//! Each copy runs inside with_user_access, the window in which the kernel may access user pages with SMAP enabled.
+//! Code that has to access user pages through user addresses must do so inside it as well.
+
+use core::marker::PhantomData;
+use core::mem::{self, MaybeUninit};
+use core::ptr::copy_nonoverlapping;
+use core::{cmp, slice};
+use x86::bits32::eflags::{clac, stac};
+use interrupts::{self, PAGE_FAULT_PROTECTION, PAGE_FAULT_WRITE};
+use memory_layout::{KERNEL_BASE, map_physical_virtual};
+use mmu::PAGE_SIZE;
+use paging;
+use process::{my_process, Process};
+use virtual_memory::walk_page_directory;
+
+/// Types that can be copied to and from user memory.
+/// Unsafe because every bit pattern must be a valid value, and the type must not contain padding.
+pub unsafe trait UserData: Copy {}
+
+unsafe impl UserData for u8 {}
+unsafe impl UserData for i32 {}
+unsafe impl UserData for u32 {}
+unsafe impl UserData for usize {}
+
+/// Runs access with the kernel allowed to access user pages, and returns its result.
+/// With SMAP enabled, this is the only place where the kernel may use user addresses. The window sets the alignment
+/// check flag with stac and clears it again with clac. alltraps clears the flag on every trap, so an interrupt inside
+/// the window does not extend it. Without SMAP the kernel may always access user pages.
+/// access must not fault or sleep, since the window would stay open in the code that runs meanwhile.
+pub fn with_user_access<T, F: FnOnce() -> T>(access: F) -> T {
+  if !paging::has_smap() {
+    return access();
+  }
+
+  unsafe {
+    stac();
+  }
+  let result = access();
+  unsafe {
+    clac();
+  }
+  result
+}
+
+/// Returns the kernel address of the byte at address in process's memory.
+/// The page is made present first, and writable too if write is set.
+/// Returns None if the process may not access the page that way.
+fn kernel_address(process: &mut Process, address: usize, write: bool) -> Option<usize> {
+  if address >= KERNEL_BASE {
+    return None;
+  }
+
+  loop {
+    let page_directory = process.address_space.as_mut()?.page_directory();
+    let error_code = match walk_page_directory(page_directory, address, false) {
+      Some(page_table_entry) if page_table_entry.is_present() => {
+        if page_table_entry.is_user_mode_allowed() && (!write || page_table_entry.is_writeable()) {
+          return Some(map_physical_virtual(page_table_entry.address().as_usize()) + address % PAGE_SIZE);
+        }
+        PAGE_FAULT_PROTECTION
+      }
+      _ => 0
+    };
+
+    let error_code = if write { error_code | PAGE_FAULT_WRITE } else { error_code };
+    if !interrupts::handle_user_page_fault(process, address, error_code) {
+      return None;
+    }
+  }
+}
+
+/// Calls copy for each page of [address, address + length) in the current process's memory, with the page's
+/// kernel address, the offset of the page's part in the range and its length.
+/// Returns false if the process may not access part of the range.
+fn for_each_page<F: FnMut(usize, usize, usize)>(address: usize, length: usize, write: bool, mut copy: F) -> bool {
+  let process = match my_process() {
+    Some(process) => process,
+    None => {
+      return false;
+    }
+  };
+
+  let mut offset = 0;
+  while offset < length {
+    let user_address = address + offset;
+    let kernel_address = match kernel_address(process, user_address, write) {
+      Some(kernel_address) => kernel_address,
+      None => {
+        return false;
+      }
+    };
+
+    let page_length = cmp::min(length - offset, PAGE_SIZE - user_address % PAGE_SIZE);
+    copy(kernel_address, offset, page_length);
+    offset += page_length;
+  }
+  true
+}
+
+/// Copies destination.len() bytes from address in the current process's memory.
+/// Returns false if the process may not read part of the range.
+pub fn copy_from_user(destination: &mut [u8], address: usize) -> bool {
+  for_each_page(address, destination.len(), false, |kernel_address, offset, length| unsafe {
+    with_user_access(|| copy_nonoverlapping(kernel_address as *const u8, destination[offset..].as_mut_ptr(), length));
+  })
+}
+
+/// Copies source to address in the current process's memory.
+/// Returns false if the process may not write part of the range. The part before it may have been written.
+pub fn copy_to_user(address: usize, source: &[u8]) -> bool {
+  for_each_page(address, source.len(), true, |kernel_address, offset, length| unsafe {
+    with_user_access(|| copy_nonoverlapping(source[offset..].as_ptr(), kernel_address as *mut u8, length));
+  })
+}
+
+/// Copies the null terminated string at address in the current process's memory into buffer.
+/// Returns the string without the terminator, or None if the process may not read it or it does not end within
+/// buffer.len() bytes.
+pub fn fetch_str(address: usize, buffer: &mut [u8]) -> Option<&[u8]> {
+  let process = my_process()?;
+
+  let mut length = 0;
+  while length < buffer.len() {
+    let user_address = address.checked_add(length)?;
+    let kernel_address = kernel_address(process, user_address, false)?;
+
+    let page_length = cmp::min(buffer.len() - length, PAGE_SIZE - user_address % PAGE_SIZE);
+    let page = unsafe { slice::from_raw_parts(kernel_address as *const u8, page_length) };
+    let end = with_user_access(|| {
+      let end = page.iter().position(|&byte| byte == 0);
+      let copied = end.unwrap_or(page_length);
+      buffer[length..length + copied].copy_from_slice(&page[..copied]);
+      end
+    });
+    match end {
+      Some(end) => {
+        return Some(&buffer[..length + end]);
+      }
+      None => {
+        length += page_length;
+      }
+    }
+  }
+  None
+}
+
+/// The address of a T in user memory.
+#[derive(Copy, Clone)]
+pub struct UserPtr<T: UserData> {
+  address: usize,
+  data: PhantomData<*mut T>
+}
+
+impl<T: UserData> UserPtr<T> {
+  pub const fn new(address: usize) -> UserPtr<T> {
+    UserPtr { address, data: PhantomData }
+  }
+
+  pub fn address(&self) -> usize {
+    self.address
+  }
+
+  pub fn is_null(&self) -> bool {
+    self.address == 0
+  }
+
+  /// Returns a pointer to the T count elements after this one, or None if the address overflows.
+  pub fn offset(&self, count: usize) -> Option<UserPtr<T>> {
+    let address = count.checked_mul(mem::size_of::<T>())?.checked_add(self.address)?;
+    Some(UserPtr::new(address))
+  }
+
+  /// Copies the T from user memory.
+  /// Returns None if the process may not read it.
+  pub fn read(&self) -> Option<T> {
+    let mut value = MaybeUninit::<T>::uninit();
+    let bytes = unsafe { slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, mem::size_of::<T>()) };
+    if !copy_from_user(bytes, self.address) {
+      return None;
+    }
+    Some(unsafe { value.assume_init() })
+  }
+
+  /// Copies value to user memory.
+  /// Returns false if the process may not write it.
+  pub fn write(&self, value: &T) -> bool {
+    let bytes = unsafe { slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>()) };
+    copy_to_user(self.address, bytes)
+  }
+}
+
+/// A range of bytes in user memory.
+#[derive(Copy, Clone)]
+pub struct UserSlice {
+  address: usize,
+  length: usize
+}
+
+impl UserSlice {
+  /// Returns None if the range does not end below KERNEL_BASE.
+  /// Whether the process may access the range is only checked when it is read or written.
+  pub fn new(address: usize, length: usize) -> Option<UserSlice> {
+    if address.checked_add(length)? > KERNEL_BASE {
+      return None;
+    }
+    Some(UserSlice { address, length })
+  }
+
+  pub fn address(&self) -> usize {
+    self.address
+  }
+
+  pub fn len(&self) -> usize {
+    self.length
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.length == 0
+  }
+
+  /// Copies the first buffer.len() bytes of the range into buffer.
+  /// Returns false if buffer is longer than the range or the process may not read it.
+  pub fn read(&self, buffer: &mut [u8]) -> bool {
+    buffer.len() <= self.length && copy_from_user(buffer, self.address)
+  }
+
+  /// Copies data to the start of the range.
+  /// Returns false if data is longer than the range or the process may not write it.
+  pub fn write(&self, data: &[u8]) -> bool {
+    data.len() <= self.length && copy_to_user(self.address, data)
+  }
+}
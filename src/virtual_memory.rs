@@ -1,17 +1,21 @@
-use core::arch::asm;
+use core::mem;
+use core::ptr::{copy_nonoverlapping, null};
 use core::slice;
-use x86::bits32::paging::{PAddr, PD, PDEntry, PDFlags, PT, PTEntry, PTFlags};
-use x86::controlregs::cr3_write;
+use x86::controlregs::{cr0, cr0_write, Cr0};
 use x86::dtables::{DescriptorTablePointer, lgdt};
 use x86::Ring;
-use x86::segmentation::{CodeSegmentType, DataSegmentType, Descriptor};
+use x86::segmentation::{CodeSegmentType, DataSegmentType, Descriptor, SegmentSelector, SystemDescriptorTypes32};
+use x86::task::load_tr;
+use x86::tlb;
 use ::{memory_layout, mmu};
-use ::{console, process};
+use ::{console, kernel_stack, paging, process, swap};
 use console::print;
-use memory_layout::{DEVICE_SPACE, EXTENDED_MEMORY, KERNEL_BASE, KERNEL_LINK, map_virtual_to_physical, PHYSICAL_TOP};
-use mmu::{page_round_up, PAGE_SIZE, SEGMENT_KERNEL_CODE, SEGMENT_KERNEL_DATA, SEGMENT_USER_CODE, SEGMENT_USER_DATA};
-use page_allocator::FREE_PAGE_LIST;
-use process::Cpu;
+use memory_layout::{DEVICE_SPACE, EXTENDED_MEMORY, KERNEL_BASE, KERNEL_LINK, map_physical_virtual, map_virtual_to_physical, PHYSICAL_TOP, USER_HEAP_LIMIT, USER_STACK_LIMIT, USER_STACK_TOP};
+use mmu::{LARGE_PAGE_SIZE, page_round_down, page_round_up, PAGE_DIRECTORY_INDEX_SHIFT, PAGE_SIZE, PTE_COPY_ON_WRITE, SEGMENT_DOUBLE_FAULT_TASK_STATE, SEGMENT_KERNEL_CODE, SEGMENT_KERNEL_DATA, SEGMENT_PROCESS_TASK_STATE, SEGMENT_USER_CODE, SEGMENT_USER_DATA, TaskState};
+use page_allocator::{FREE_PAGE_LIST, PageOwner};
+use paging::{PAddr, PAGE_DIRECTORY_ORDER, PD, PDEntry, PDFlags, PT, PTEntry, PTFlags};
+use param::KERNEL_STACK_SIZE;
+use process::{Cpu, get_current_cpu_id, Process};
 
 struct KernelMap {
   virtual_address: usize,
@@ -20,21 +24,38 @@ struct KernelMap {
   perm: PTFlags
 }
 
+// The page aligned starts of the kernel image's sections, from kernel.ld.
 extern {
+  static rodata: usize;
   static data: usize;
+  static bss: usize;
 }
 
 /// Creates a page directory and the corresponding page tables need for the kernel's virtual memory mappings.
+/// Each part of the kernel image is mapped with the least permissions it needs: text is read-only, read-only data
+/// is also not executable, and only data and bss are writable.
+/// The mappings are global, since every address space shares them.
 pub fn setup_kernel_virtual_memory() -> Option<&'static mut PD> {
-  let tmp: usize = unsafe { &data as *const usize as usize };
-  let map: [KernelMap; 4] = [
-    KernelMap {virtual_address: KERNEL_BASE, phys_start: 0, phys_end: EXTENDED_MEMORY, perm: PTFlags::RW},
-    KernelMap {virtual_address: KERNEL_LINK, phys_start: map_virtual_to_physical(KERNEL_LINK), phys_end: map_virtual_to_physical(tmp), perm: PTFlags::empty()},
-    KernelMap {virtual_address: tmp, phys_start: map_virtual_to_physical(tmp), phys_end: PHYSICAL_TOP, perm: PTFlags::RW},
-    KernelMap {virtual_address: DEVICE_SPACE, phys_start: DEVICE_SPACE, phys_end: 0, perm: PTFlags::RW},
+  let rodata_start: usize = unsafe { &rodata as *const usize as usize };
+  let data_start: usize = unsafe { &data as *const usize as usize };
+  let bss_start: usize = unsafe { &bss as *const usize as usize };
+  let global = paging::global();
+  let map: [KernelMap; 6] = [
+    // I/O space
+    KernelMap {virtual_address: KERNEL_BASE, phys_start: 0, phys_end: EXTENDED_MEMORY, perm: PTFlags::RW | paging::no_execute() | global},
+    // Kernel text
+    KernelMap {virtual_address: KERNEL_LINK, phys_start: map_virtual_to_physical(KERNEL_LINK), phys_end: map_virtual_to_physical(rodata_start), perm: global},
+    // Kernel read-only data
+    KernelMap {virtual_address: rodata_start, phys_start: map_virtual_to_physical(rodata_start), phys_end: map_virtual_to_physical(data_start), perm: paging::no_execute() | global},
+    // Kernel data
+    KernelMap {virtual_address: data_start, phys_start: map_virtual_to_physical(data_start), phys_end: map_virtual_to_physical(bss_start), perm: PTFlags::RW | paging::no_execute() | global},
+    // Kernel bss and the free memory after the kernel
+    KernelMap {virtual_address: bss_start, phys_start: map_virtual_to_physical(bss_start), phys_end: PHYSICAL_TOP, perm: PTFlags::RW | paging::no_execute() | global},
+    // Memory mapped devices
+    KernelMap {virtual_address: DEVICE_SPACE, phys_start: DEVICE_SPACE, phys_end: 0, perm: PTFlags::RW | paging::no_execute() | global},
   ];
 
-  let page_location = unsafe {FREE_PAGE_LIST.alloc_page()};
+  let page_location = FREE_PAGE_LIST.alloc_pages(PAGE_DIRECTORY_ORDER, PageOwner::PageDirectory);
   if page_location.is_none() {
     return None;
   }
@@ -57,22 +78,60 @@ pub fn setup_kernel_virtual_memory() -> Option<&'static mut PD> {
     let map_result = map_pages(page_directory, mapping.virtual_address, sz,
                                mapping.phys_start, mapping.perm);
     if map_result == false {
-      free_virtual_memory(page_directory);
+      free_page_directory(page_directory, mmu::PAGE_DIRECTORY_ENTRIES);
       return None;
     }
 
   }
 
+  // Every page directory shares the page tables of the kernel stacks.
+  kernel_stack::install(page_directory);
+
+  Some(page_directory)
+}
+
+/// Creates a page directory for a user address space.
+/// The kernel half is not mapped again. Its entries are copied from the kernel page directory, so every address
+/// space shares the kernel's page tables. Must be called after kmalloc.
+pub fn setup_user_virtual_memory() -> Option<&'static mut PD> {
+  let kernel = unsafe { &*kernel_page_directory };
+  let page_directory = unsafe { &mut *(FREE_PAGE_LIST.alloc_pages(PAGE_DIRECTORY_ORDER, PageOwner::PageDirectory)? as *mut PD) };
+
+  let kernel_start = mmu::page_directory_index(KERNEL_BASE);
+  for (index, page_directory_entry) in page_directory.iter_mut().enumerate() {
+    *page_directory_entry = if index < kernel_start {
+      PDEntry(0)
+    } else {
+      kernel[index]
+    };
+  }
+
   Some(page_directory)
 }
 
 /// Creates page table entries for a virtual_address.
-fn map_pages(page_directory: &mut PD, virtual_address: usize, size: usize, physical_address: usize, permissions: PTFlags) -> bool {
+/// Kernel ranges use a 4MiB page directory entry wherever a whole large page fits.
+pub fn map_pages(page_directory: &mut PD, virtual_address: usize, size: usize, physical_address: usize, permissions: PTFlags) -> bool {
   let mut physical_address = physical_address;
   let mut address = mmu::page_round_down(virtual_address);
   let last = mmu::page_round_down(virtual_address.overflowing_add(size).0.overflowing_sub(1).0);
   //println!("({:x}, {:x})", address, last);
   loop {
+    if is_large_page_range(address, last, physical_address, permissions) {
+      let page_directory_entry = &mut page_directory[mmu::page_directory_index(address)];
+      if page_directory_entry.is_present() {
+        panic!("Remap!");
+      }
+      let flags = PDFlags::from_bits_truncate(permissions.bits() & (PTFlags::RW.bits() | PTFlags::G.bits() | paging::NO_EXECUTE_BIT));
+      *page_directory_entry = PDEntry::new(PAddr::from(physical_address), PDFlags::P | PDFlags::PS | flags);
+      if last - address == LARGE_PAGE_SIZE - PAGE_SIZE {
+        return true;
+      }
+      address = address.overflowing_add(LARGE_PAGE_SIZE).0;
+      physical_address = physical_address.overflowing_add(LARGE_PAGE_SIZE).0;
+      continue;
+    }
+
     match walk_page_directory(page_directory, address, true) {
       Some(page_table_entry) => {
         if page_table_entry.is_present() {
@@ -95,16 +154,30 @@ fn map_pages(page_directory: &mut PD, virtual_address: usize, size: usize, physi
   return true;
 }
 
+/// Returns true if map_pages can map the large page at address to physical_address, with last being the address of
+/// the last page to map.
+/// Only kernel ranges use large pages, since user pages are reference counted and swapped one page at a time.
+fn is_large_page_range(address: usize, last: usize, physical_address: usize, permissions: PTFlags) -> bool {
+  paging::has_large_pages()
+    && !permissions.contains(PTFlags::US)
+    && address % LARGE_PAGE_SIZE == 0
+    && physical_address % LARGE_PAGE_SIZE == 0
+    && last - address >= LARGE_PAGE_SIZE - PAGE_SIZE
+}
+
 
 /// Checks if there is an entry in the page directory for page table.
 /// If the page table already exists, then return a reference to the page table entry for virtual_address.
 /// If there is no entry and allocate is false, then None is returned.
 /// If there is no entry and allocate is true, then allocate memory for the page table and return the reference to the page table entry for virtual_address.
-fn walk_page_directory(page_table: &mut PD, virtual_address: usize, allocate: bool) -> Option<&mut PTEntry> {
+/// A virtual_address in a 4MiB page has no page table entry, so None is returned.
+pub fn walk_page_directory(page_table: &mut PD, virtual_address: usize, allocate: bool) -> Option<&mut PTEntry> {
   let page_directory_entry = &mut page_table[mmu::page_directory_index(virtual_address)];
   let page_table: &mut PT;
 
-  if page_directory_entry.is_present() {
+  if page_directory_entry.flags().contains(PDFlags::P | PDFlags::PS) {
+    return None;
+  } else if page_directory_entry.is_present() {
     unsafe {
       page_table = &mut *(memory_layout::map_physical_virtual(page_directory_entry.address().as_usize()) as *mut PT);
     }
@@ -113,7 +186,7 @@ fn walk_page_directory(page_table: &mut PD, virtual_address: usize, allocate: bo
       return None;
     }
 
-    let page_location = unsafe {FREE_PAGE_LIST.alloc_page()};
+    let page_location = FREE_PAGE_LIST.alloc_page(PageOwner::PageTable);
     if page_location.is_none() {
       return None;
     }
@@ -134,30 +207,240 @@ fn walk_page_directory(page_table: &mut PD, virtual_address: usize, allocate: bo
   Some(&mut page_table[mmu::page_table_index(virtual_address)])
 }
 
-/// Free a page table and all the physical memory pages.
-fn free_virtual_memory(page_directory: &mut PD) {
-  for page_directory_entry in page_directory.into_iter() {
-    if page_directory_entry.is_present() {
+/// Frees a user page directory from setup_user_virtual_memory with everything it maps, like freevm.
+/// Drops a reference to every user page and swap slot, then frees the user page tables and the page directory.
+/// The kernel's page tables are shared and stay.
+/// The process's mmap areas must be removed with vma::unmap_all first, so that their files and shared memory
+/// segments are released.
+pub fn free_virtual_memory(page_directory: &mut PD) {
+  swap::remove_address_space(page_directory);
+  release_user_pages(page_directory, 0, KERNEL_BASE);
+  free_page_directory(page_directory, mmu::page_directory_index(KERNEL_BASE));
+}
+
+/// Frees the page tables of the first entries of a page directory, then the page directory itself.
+fn free_page_directory(page_directory: &mut PD, entries: usize) {
+  for (index, page_directory_entry) in page_directory.iter().take(entries).enumerate() {
+    // The kernel stacks' page tables belong to every page directory, and a large page has no page table.
+    if page_directory_entry.is_present() && !page_directory_entry.flags().contains(PDFlags::PS)
+      && !kernel_stack::is_stack_page_directory_index(index) {
       unsafe {
         FREE_PAGE_LIST.dealloc_page(memory_layout::map_physical_virtual(page_directory_entry.address().as_usize()))
       }
     }
   }
   unsafe {
-    FREE_PAGE_LIST.dealloc_page(memory_layout::map_physical_virtual(page_directory as *mut PD as usize));
+    FREE_PAGE_LIST.dealloc_pages(page_directory as *mut PD as usize, PAGE_DIRECTORY_ORDER);
   }
 }
 
+/// Unmaps the user pages in [page_round_up(start), end) and drops a reference to each of them.
+/// Pages that are no longer shared with another page directory are freed.
+pub fn release_user_pages(page_directory: &mut PD, start: usize, end: usize) {
+  let mut address = page_round_up(start);
+  while address < end {
+    let page_table_entry = match walk_page_directory(page_directory, address, false) {
+      Some(page_table_entry) => page_table_entry,
+      None => {
+        // There is no page table here, so skip to the next page directory entry.
+        address = ((address >> PAGE_DIRECTORY_INDEX_SHIFT) + 1) << PAGE_DIRECTORY_INDEX_SHIFT;
+        continue;
+      }
+    };
+
+    if page_table_entry.is_present() {
+      unsafe {
+        FREE_PAGE_LIST.remove_reference(map_physical_virtual(page_table_entry.address().as_usize()));
+        *page_table_entry = PTEntry(0);
+        tlb::flush(address);
+      }
+    } else if swap::is_swapped(page_table_entry) {
+      swap::release_slot(page_table_entry);
+      *page_table_entry = PTEntry(0);
+    }
+    address += PAGE_SIZE;
+  }
+}
+
+/// Grows the user memory from old_size to new_size with zeroed pages, like allocuvm.
+/// Unlike grow_process, the pages are allocated now, for example for exec to load a program into.
+/// Returns the new size, or None if new_size is above USER_HEAP_LIMIT or there is not enough memory. On failure,
+/// the pages allocated so far are released again.
+pub fn allocate_user_virtual_memory(page_directory: &mut PD, old_size: usize, new_size: usize) -> Option<usize> {
+  if new_size > USER_HEAP_LIMIT {
+    return None;
+  }
+  if new_size < old_size {
+    return Some(old_size);
+  }
+
+  let mut address = page_round_up(old_size);
+  while address < new_size {
+    if !map_zeroed_page(page_directory, address) {
+      deallocate_user_virtual_memory(page_directory, new_size, old_size);
+      return None;
+    }
+    address += PAGE_SIZE;
+  }
+  Some(new_size)
+}
+
+/// Shrinks the user memory from old_size to new_size, like deallocuvm.
+/// The pages above new_size are unmapped and their references dropped.
+/// Returns the new size.
+pub fn deallocate_user_virtual_memory(page_directory: &mut PD, old_size: usize, new_size: usize) -> usize {
+  if new_size >= old_size {
+    return old_size;
+  }
+
+  release_user_pages(page_directory, new_size, old_size);
+  new_size
+}
+
+/// Maps a zeroed user page at the page containing virtual_address.
+/// Used by the page fault handler to back heap and stack pages on their first use.
+/// Returns false if there is no memory for the page or its page table.
+pub fn map_zeroed_page(page_directory: &mut PD, virtual_address: usize) -> bool {
+  let page = match FREE_PAGE_LIST.alloc_page(PageOwner::UserPage) {
+    Some(page) => page,
+    None => {
+      return false;
+    }
+  };
+
+  unsafe {
+    (page as *mut u8).write_bytes(0, PAGE_SIZE);
+  }
+
+  if !map_user_page(page_directory, virtual_address, page, PTFlags::RW | PTFlags::US | paging::no_execute()) {
+    unsafe {
+      FREE_PAGE_LIST.dealloc_page(page);
+    }
+    return false;
+  }
+
+  true
+}
+
+/// Maps page, a kernel virtual address returned by the page allocator, at the page containing virtual_address.
+/// The page directory takes over the caller's reference to the page.
+/// Returns false if there is no memory for the page table.
+pub fn map_user_page(page_directory: &mut PD, virtual_address: usize, page: usize, permissions: PTFlags) -> bool {
+  map_pages(page_directory, page_round_down(virtual_address), PAGE_SIZE, map_virtual_to_physical(page), permissions)
+}
+
+/// Creates a copy of a user address space for fork without copying any pages, like copyuvm.
+/// The copy has the user memory [0, size) and the stack. The mmap areas are copied by vma::copy_areas.
+/// The parent and child share every page. Writable pages are made read-only and marked copy-on-write in both
+/// page directories, so that the first write to a shared page faults and handle_copy_on_write copies it.
+/// Returns the child's page directory, or None if there is not enough memory for its page tables or swap can not
+/// take another address space.
+pub fn copy_on_write_user_virtual_memory(page_directory: &mut PD, size: usize) -> Option<&'static mut PD> {
+  let child_page_directory = setup_user_virtual_memory()?;
+
+  if !share_user_pages(page_directory, child_page_directory, 0, size, true)
+    || !share_user_pages(page_directory, child_page_directory, USER_STACK_LIMIT, USER_STACK_TOP, true)
+    || !swap::add_address_space(child_page_directory) {
+    free_virtual_memory(child_page_directory);
+    return None;
+  }
+  Some(child_page_directory)
+}
+
+/// Maps the user pages of page_directory in [start, end) into child_page_directory at the same addresses.
+/// If copy_on_write is set, writable pages become copy-on-write in both page directories. Otherwise both keep
+/// writing to the same pages, as MAP_SHARED areas do.
+/// Returns false if there is not enough memory for the child's page tables.
+pub fn share_user_pages(page_directory: &mut PD, child_page_directory: &mut PD, start: usize, end: usize, copy_on_write: bool) -> bool {
+  let mut address = start;
+  while address < end {
+    if !copy_on_write {
+      // Whoever faults on a shared swap slot reads their own copy back in, so shared pages are brought back first.
+      swap::swap_in(page_directory, address);
+    }
+
+    if let Some(page_table_entry) = walk_page_directory(page_directory, address, false) {
+      let present = page_table_entry.is_present();
+      if present || swap::is_swapped(page_table_entry) {
+        if present && copy_on_write && page_table_entry.is_writeable() {
+          page_table_entry.0 = (page_table_entry.0 & !PTFlags::RW.bits()) | PTE_COPY_ON_WRITE;
+        }
+
+        match walk_page_directory(child_page_directory, address, true) {
+          Some(child_page_table_entry) => {
+            *child_page_table_entry = *page_table_entry;
+          }
+          None => {
+            unsafe {
+              tlb::flush_all();
+            }
+            return false;
+          }
+        }
+
+        if present {
+          FREE_PAGE_LIST.add_reference(map_physical_virtual(page_table_entry.address().as_usize()));
+        } else {
+          swap::share_slot(page_table_entry);
+        }
+      }
+    }
+    address += PAGE_SIZE;
+  }
+
+  // The parent's writable pages may now be read-only.
+  unsafe {
+    tlb::flush_all();
+  }
+  true
+}
+
+/// Resolves a write fault on a copy-on-write page.
+/// If another page directory still shares the page, the faulting page directory gets its own copy.
+/// Otherwise this is the last reference and the page is simply made writable again.
+/// Returns false if the page is not copy-on-write, or if there is no memory for the copy.
+pub fn handle_copy_on_write(page_directory: &mut PD, virtual_address: usize) -> bool {
+  let page_table_entry = match walk_page_directory(page_directory, page_round_down(virtual_address), false) {
+    Some(page_table_entry) if page_table_entry.is_present() && page_table_entry.0 & PTE_COPY_ON_WRITE != 0 => {
+      page_table_entry
+    }
+    _ => {
+      return false;
+    }
+  };
+
+  let page = map_physical_virtual(page_table_entry.address().as_usize());
+  unsafe {
+    if FREE_PAGE_LIST.reference_count(page) > 1 {
+      let copy = match FREE_PAGE_LIST.alloc_page(PageOwner::UserPage) {
+        Some(copy) => copy,
+        None => {
+          return false;
+        }
+      };
+      copy_nonoverlapping(page as *const u8, copy as *mut u8, PAGE_SIZE);
+      FREE_PAGE_LIST.remove_reference(page);
+
+      *page_table_entry = PTEntry::new(PAddr::from(map_virtual_to_physical(copy)), page_table_entry.flags());
+    }
+
+    page_table_entry.0 = (page_table_entry.0 | PTFlags::RW.bits()) & !PTE_COPY_ON_WRITE;
+    tlb::flush(page_round_down(virtual_address));
+  }
+
+  true
+}
+
 
 // Load the init_code into address 0 of page_directory.
 // size must be less than a page.
-fn init_user_virtual_memory(page_directory: &mut PD, init_code: usize, size: usize) {
+pub(crate) fn init_user_virtual_memory(page_directory: &mut PD, init_code: usize, size: usize) {
   let mem: *mut u8;
   if size >= PAGE_SIZE {
     panic!("init_uuser_virtual_memory: more than a page");
   }
 
-  let page_location = unsafe {FREE_PAGE_LIST.alloc_page()};
+  let page_location = FREE_PAGE_LIST.alloc_page(PageOwner::UserPage);
   if page_location.is_none() {
     panic!("No more memory.");
   }
@@ -180,48 +463,77 @@ static mut kernel_page_directory: *mut PD = 0 as *mut PD;
 /// Allocate one page table for the machine for the kernel address space for scheduler processes.
 /// After this call all kernel code and peripherals will be mapped to higher memory.
 pub unsafe fn kmalloc() {
+  paging::init();
   kernel_page_directory = setup_kernel_virtual_memory().expect("No kernel page table");
-  switchkvm();
+  paging::enable(&*kernel_page_directory);
+  // Writes to read-only pages fault in ring 0 too, so stray writes to kernel text or constants are caught.
+  cr0_write(cr0() | Cr0::CR0_WRITE_PROTECT);
   console::switch_to_virtual_memory();
   println!("Kernel memory allocated. Mapped to higher address space.")
 }
 
 
 // Switch h/w page table register to the kernel-only page table, for when no process is running.
-unsafe fn switchkvm()  {
-  let page_directory_address = map_virtual_to_physical(kernel_page_directory as usize);
-  asm!("mov {0}, %cr3", in(reg) page_directory_address as usize, options(att_syntax));
+pub(crate) unsafe fn switchkvm()  {
+  swap::set_loaded_address_space(null());
+  paging::load_page_directory(&*kernel_page_directory);
+}
+
+/// Switches the task state to the kernel stack of process and the h/w page table register to its address space.
+/// Must be called with interrupts disabled.
+pub(crate) unsafe fn switchuvm(process: &Process) {
+  let cpu = process::get_current_cpu();
+  cpu.ts.set_kernel_stack((SEGMENT_KERNEL_DATA << 3) as u16, (process.kernel_stack + KERNEL_STACK_SIZE) as u32);
+  process.address_space.as_ref().expect("switchuvm: no address space").activate();
 }
 
 /// Sets up segmentation for a cpu core. Called once for each cpu.
 /// The primary reason for using segmentation is for per cpu variables.
 /// On the pentium segmentation happens before paging.
 pub(crate) unsafe fn setup_segmentation() {
+  let cpu_id = get_current_cpu_id() as usize;
   let cpu: &mut Cpu = process::get_current_cpu();
 
-  cpu.gdt[SEGMENT_KERNEL_CODE] = Descriptor::default();
-  cpu.gdt[SEGMENT_KERNEL_CODE].set_type(CodeSegmentType::ExecuteRead as u8);
-  cpu.gdt[SEGMENT_KERNEL_CODE].set_base_limit(0, 0xffffffff);
-  cpu.gdt[SEGMENT_KERNEL_CODE].set_dpl(Ring::Ring0);
-
-  cpu.gdt[SEGMENT_KERNEL_DATA] = Descriptor::default();
-  cpu.gdt[SEGMENT_KERNEL_DATA].set_type(DataSegmentType::ReadWrite as u8);
-  cpu.gdt[SEGMENT_KERNEL_DATA].set_base_limit(0, 0xffffffff);
-  cpu.gdt[SEGMENT_KERNEL_DATA].set_dpl(Ring::Ring0);
+  cpu.gdt[SEGMENT_KERNEL_CODE] = segment_descriptor(CodeSegmentType::ExecuteRead as u8, Ring::Ring0);
+  cpu.gdt[SEGMENT_KERNEL_DATA] = segment_descriptor(DataSegmentType::ReadWrite as u8, Ring::Ring0);
+  cpu.gdt[SEGMENT_USER_CODE] = segment_descriptor(CodeSegmentType::ExecuteRead as u8, Ring::Ring3);
+  cpu.gdt[SEGMENT_USER_DATA] = segment_descriptor(DataSegmentType::ReadWrite as u8, Ring::Ring3);
 
-  cpu.gdt[SEGMENT_USER_CODE] = Descriptor::default();
-  cpu.gdt[SEGMENT_USER_CODE].set_type(CodeSegmentType::ExecuteRead as u8);
-  cpu.gdt[SEGMENT_USER_CODE].set_base_limit(0, 0xffffffff);
-  cpu.gdt[SEGMENT_USER_CODE].set_dpl(Ring::Ring3);
+  // The task register must always hold a valid task state, because a task switch saves the current task into it.
+  cpu.ts.set_kernel_stack((SEGMENT_KERNEL_DATA << 3) as u16, 0);
+  cpu.gdt[SEGMENT_PROCESS_TASK_STATE] = task_state_descriptor(&cpu.ts);
 
-  cpu.gdt[SEGMENT_USER_DATA] = Descriptor::default();
-  cpu.gdt[SEGMENT_USER_DATA].set_type(DataSegmentType::ReadWrite as u8);
-  cpu.gdt[SEGMENT_USER_DATA].set_base_limit(0, 0xffffffff);
-  cpu.gdt[SEGMENT_USER_DATA].set_dpl(Ring::Ring3);
+  // Double faults switch to a task with its own stack, so that a kernel stack overflow can be reported.
+  let page_directory = paging::page_directory_base(cpu_id, &*kernel_page_directory);
+  cpu.gdt[SEGMENT_DOUBLE_FAULT_TASK_STATE] = task_state_descriptor(kernel_stack::double_fault_task_state(cpu_id, page_directory));
 
   let gdt_pointer = DescriptorTablePointer::new(&cpu.gdt);
 
   lgdt(&gdt_pointer);
+  load_tr(SegmentSelector::new(SEGMENT_PROCESS_TASK_STATE as u16, Ring::Ring0));
 
   println!("Segmentation setup.");
-}
\ No newline at end of file
+}
+
+/// Creates a flat 4GiB code or data segment descriptor, like the SEG macro.
+fn segment_descriptor(segment_type: u8, ring: Ring) -> Descriptor {
+  let mut descriptor = Descriptor::default();
+  descriptor.set_type(segment_type);
+  descriptor.set_base_limit(0, 0xfffff);
+  descriptor.set_dpl(ring);
+  descriptor.set_s();
+  descriptor.set_p();
+  descriptor.set_db();
+  descriptor.set_g();
+  descriptor
+}
+
+/// Creates the descriptor of a task state segment.
+fn task_state_descriptor(task_state: &TaskState) -> Descriptor {
+  let mut descriptor = Descriptor::default();
+  descriptor.set_type(SystemDescriptorTypes32::TssAvailable32 as u8);
+  descriptor.set_base_limit(task_state as *const TaskState as u32, (mem::size_of::<TaskState>() - 1) as u32);
+  descriptor.set_dpl(Ring::Ring0);
+  descriptor.set_p();
+  descriptor
+}
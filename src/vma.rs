@@ -0,0 +1,488 @@
+//! # Virtual Memory Areas
+//! The mappings created by mmap. Each process has a small table of areas placed between USER_MMAP_BASE and
+//! USER_MMAP_TOP. mmap only records an area. The page fault handler fills in each page on its first use: anonymous
+//! areas get zeroed pages and file backed areas read the page's contents through the file's inode.
+//! After fork, the pages of a private area are copied on write, while both processes keep writing to the same pages
+//! of a shared area. Dirty pages of a shared file backed area are written back to the file when they are unmapped.
+//! Shared memory segments attached with shmat are shared areas that map the segment's pages.
+
+use core::slice;
+use x86::tlb;
+use file::File;
+use memory_layout::{map_physical_virtual, USER_MMAP_BASE, USER_MMAP_TOP};
+use mmu::{page_round_down, page_round_up, PAGE_SIZE};
+use page_allocator::{FREE_PAGE_LIST, PageOwner};
+use paging::{self, PD, PTFlags};
+use process::Process;
+use shm;
+use swap;
+use virtual_memory::{self, walk_page_directory};
+
+// Protection bits.
+pub const PROT_NONE: u32 = 0x0;
+pub const PROT_READ: u32 = 0x1;
+pub const PROT_WRITE: u32 = 0x2;
+pub const PROT_EXEC: u32 = 0x4;
+
+// Mapping flags.
+pub const MAP_SHARED: u32 = 0x01;
+pub const MAP_PRIVATE: u32 = 0x02;
+pub const MAP_FIXED: u32 = 0x10;
+pub const MAP_ANONYMOUS: u32 = 0x20;
+
+/// What fills the pages of an area.
+#[derive(Copy, Clone)]
+pub enum Backing {
+  /// Zeroed pages.
+  Anonymous,
+  /// The contents of an open file. offset is the file offset of the area's start.
+  File { file: *mut File, offset: usize },
+  /// The pages of a shared memory segment. offset is the segment offset of the area's start.
+  SharedMemory { segment: usize, offset: usize }
+}
+
+/// A range of user memory created by mmap.
+#[derive(Copy, Clone)]
+pub struct VirtualMemoryArea {
+  /// The first address of the area. Page aligned.
+  pub start: usize,
+  /// The address after the last page of the area. Page aligned.
+  pub end: usize,
+  /// PROT_* bits.
+  pub protection: u32,
+  /// MAP_SHARED or MAP_PRIVATE.
+  pub sharing: u32,
+  pub backing: Backing
+}
+
+impl VirtualMemoryArea {
+
+  fn contains(&self, address: usize) -> bool {
+    self.start <= address && address < self.end
+  }
+
+  fn overlaps(&self, start: usize, end: usize) -> bool {
+    self.start < end && start < self.end
+  }
+
+  fn is_shared(&self) -> bool {
+    self.sharing == MAP_SHARED
+  }
+
+  /// The page table entry permissions of a newly filled page.
+  fn permissions(&self) -> PTFlags {
+    let mut permissions = PTFlags::empty();
+    if self.protection != PROT_NONE {
+      permissions |= PTFlags::US;
+    }
+    if self.protection & PROT_WRITE != 0 {
+      permissions |= PTFlags::RW;
+    }
+    if self.protection & PROT_EXEC == 0 {
+      permissions |= paging::no_execute();
+    }
+    permissions
+  }
+
+  /// Adds a reference to the area's file or shared memory segment, for a new area.
+  fn dup_backing(&self) {
+    match self.backing {
+      Backing::Anonymous => {}
+      Backing::File { file, .. } => unsafe {
+        (*file).dup();
+      }
+      Backing::SharedMemory { segment, .. } => {
+        shm::attach_area(segment);
+      }
+    }
+  }
+
+  /// Drops the area's reference to its file or shared memory segment.
+  fn close_backing(&self) {
+    match self.backing {
+      Backing::Anonymous => {}
+      Backing::File { file, .. } => unsafe {
+        (*file).close();
+      }
+      Backing::SharedMemory { segment, .. } => {
+        shm::detach_area(segment);
+      }
+    }
+  }
+
+  /// Cuts the area at address, a page boundary inside it, and returns the part above address.
+  fn split(&mut self, address: usize) -> VirtualMemoryArea {
+    let mut upper = *self;
+    upper.start = address;
+    upper.backing = match self.backing {
+      Backing::Anonymous => Backing::Anonymous,
+      Backing::File { file, offset } => Backing::File { file, offset: offset + (address - self.start) },
+      Backing::SharedMemory { segment, offset } => Backing::SharedMemory { segment, offset: offset + (address - self.start) }
+    };
+    upper.dup_backing();
+
+    self.end = address;
+    upper
+  }
+
+}
+
+fn find_area(process: &mut Process, address: usize) -> Option<&mut VirtualMemoryArea> {
+  process.memory_areas.iter_mut().flatten().find(|area| area.contains(address))
+}
+
+fn is_range_free(process: &Process, start: usize, end: usize) -> bool {
+  !process.memory_areas.iter().flatten().any(|area| area.overlaps(start, end))
+}
+
+/// Returns true if every page in [start, end) belongs to an area.
+fn is_range_mapped(process: &mut Process, start: usize, end: usize) -> bool {
+  let mut address = start;
+  while address < end {
+    match find_area(process, address) {
+      Some(area) => {
+        address = area.end;
+      }
+      None => {
+        return false;
+      }
+    }
+  }
+  true
+}
+
+/// Returns the lowest address in the mmap region where length bytes fit between the existing areas.
+fn find_free_range(process: &Process, length: usize) -> Option<usize> {
+  let mut start = USER_MMAP_BASE;
+  'search: while start + length <= USER_MMAP_TOP {
+    for area in process.memory_areas.iter().flatten() {
+      if area.overlaps(start, start + length) {
+        start = area.end;
+        continue 'search;
+      }
+    }
+    return Some(start);
+  }
+  None
+}
+
+/// Splits the area that contains address in two, so that address becomes a boundary between areas.
+/// Returns false if there is no free entry for the second half.
+fn split_at(process: &mut Process, address: usize) -> bool {
+  let index = match process.memory_areas.iter().position(|entry| {
+    matches!(entry, Some(area) if area.start < address && address < area.end)
+  }) {
+    Some(index) => index,
+    None => {
+      return true;
+    }
+  };
+
+  let free_index = match process.memory_areas.iter().position(|entry| entry.is_none()) {
+    Some(free_index) => free_index,
+    None => {
+      return false;
+    }
+  };
+
+  let upper = process.memory_areas[index].as_mut().unwrap().split(address);
+  process.memory_areas[free_index] = Some(upper);
+  true
+}
+
+/// Creates an area of length bytes, like mmap.
+/// # Arguments
+/// * 'address' - Where the area starts if flags has MAP_FIXED. Otherwise the area goes in the lowest free range.
+/// * 'protection' - PROT_* bits.
+/// * 'flags' - MAP_SHARED or MAP_PRIVATE, optionally with MAP_FIXED and MAP_ANONYMOUS.
+/// * 'file' - The open file to map. Ignored for MAP_ANONYMOUS.
+/// * 'offset' - The page aligned file offset of the first page.
+/// Returns the start of the area, or None if the arguments are invalid or there is no room for the area.
+pub fn mmap(process: &mut Process, address: usize, length: usize, protection: u32, flags: u32, file: Option<&mut File>, offset: usize) -> Option<usize> {
+  if length == 0 || length > USER_MMAP_TOP - USER_MMAP_BASE || offset % PAGE_SIZE != 0 {
+    return None;
+  }
+  if protection & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+    return None;
+  }
+
+  let sharing = flags & (MAP_SHARED | MAP_PRIVATE);
+  if sharing != MAP_SHARED && sharing != MAP_PRIVATE {
+    return None;
+  }
+
+  let backing = if flags & MAP_ANONYMOUS != 0 {
+    Backing::Anonymous
+  } else {
+    let open_file = file?;
+    open_file.inode()?;
+    if !open_file.is_readable() {
+      return None;
+    }
+    // Writes to a shared area end up in the file.
+    if sharing == MAP_SHARED && protection & PROT_WRITE != 0 && !open_file.is_writable() {
+      return None;
+    }
+    Backing::File { file: open_file, offset }
+  };
+
+  add_area(process, address, page_round_up(length), flags & MAP_FIXED != 0, protection, sharing, backing)
+}
+
+/// Maps a shared memory segment into the process, for shmat.
+/// # Arguments
+/// * 'address' - Where the area starts, or 0 to place it in the lowest free range.
+/// * 'length' - The page aligned size of the segment.
+/// Returns the start of the area, or None if there is no room for the area.
+pub fn map_shared_memory(process: &mut Process, address: usize, length: usize, protection: u32, segment: usize) -> Option<usize> {
+  let backing = Backing::SharedMemory { segment, offset: 0 };
+  add_area(process, address, length, address != 0, protection, MAP_SHARED, backing)
+}
+
+/// Unmaps the shared memory area that starts at address, for shmdt.
+/// Returns false if no shared memory area starts at address.
+pub fn unmap_shared_memory(process: &mut Process, address: usize) -> bool {
+  let length = match process.memory_areas.iter().flatten().find(|area| area.start == address) {
+    Some(area) if matches!(area.backing, Backing::SharedMemory { .. }) => area.end - area.start,
+    _ => {
+      return false;
+    }
+  };
+  munmap(process, address, length)
+}
+
+/// Records a new area of length bytes and takes a reference to its backing.
+/// If fixed is set, the area starts at address, which must be page aligned and free. Otherwise the area goes in the
+/// lowest free range.
+fn add_area(process: &mut Process, address: usize, length: usize, fixed: bool, protection: u32, sharing: u32, backing: Backing) -> Option<usize> {
+  let start = if fixed {
+    if address % PAGE_SIZE != 0 || address < USER_MMAP_BASE || address > USER_MMAP_TOP - length
+      || !is_range_free(process, address, address + length) {
+      return None;
+    }
+    address
+  } else {
+    find_free_range(process, length)?
+  };
+
+  let entry = process.memory_areas.iter_mut().find(|entry| entry.is_none())?;
+  let area = VirtualMemoryArea {
+    start,
+    end: start + length,
+    protection,
+    sharing,
+    backing
+  };
+  area.dup_backing();
+  *entry = Some(area);
+  Some(start)
+}
+
+/// Removes the pages in [address, address + length) from the process's areas, like munmap.
+/// Areas that only partly overlap the range are cut.
+/// Returns false if address is not page aligned or an area can not be cut.
+pub fn munmap(process: &mut Process, address: usize, length: usize) -> bool {
+  let end = match address.checked_add(length) {
+    Some(end) if length != 0 && address % PAGE_SIZE == 0 && end <= USER_MMAP_TOP => page_round_up(end),
+    _ => {
+      return false;
+    }
+  };
+
+  if !split_at(process, address) || !split_at(process, end) {
+    return false;
+  }
+
+  let page_directory = process.address_space.as_mut().expect("munmap: no address space").page_directory();
+  for entry in process.memory_areas.iter_mut() {
+    if let Some(area) = *entry {
+      if address <= area.start && area.end <= end {
+        unmap_area(page_directory, &area);
+        *entry = None;
+      }
+    }
+  }
+  true
+}
+
+/// Changes the protection of the pages in [address, address + length), like mprotect.
+/// Areas that only partly overlap the range are cut.
+/// Returns false if address is not page aligned, part of the range is not mapped, protection is invalid or an area
+/// can not be cut.
+pub fn mprotect(process: &mut Process, address: usize, length: usize, protection: u32) -> bool {
+  let end = match address.checked_add(length) {
+    Some(end) if length != 0 && address % PAGE_SIZE == 0 => page_round_up(end),
+    _ => {
+      return false;
+    }
+  };
+
+  if protection & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 || !is_range_mapped(process, address, end) {
+    return false;
+  }
+
+  // A shared file area may only become writable if the file was opened for writing.
+  for area in process.memory_areas.iter().flatten() {
+    if let Backing::File { file, .. } = area.backing {
+      if area.overlaps(address, end) && area.is_shared() && protection & PROT_WRITE != 0
+        && !unsafe { (*file).is_writable() } {
+        return false;
+      }
+    }
+  }
+
+  if !split_at(process, address) || !split_at(process, end) {
+    return false;
+  }
+
+  // Pages that are not filled in yet get the new permissions when they are.
+  let address_space = process.address_space.as_mut().expect("mprotect: no address space");
+  for area in process.memory_areas.iter_mut().flatten() {
+    if address <= area.start && area.end <= end {
+      area.protection = protection;
+      address_space.protect(area.start, area.end - area.start, area.permissions(), area.is_shared());
+    }
+  }
+  true
+}
+
+/// Resolves a page fault at address if it is inside one of the process's areas.
+/// Returns None if the address is not in an area. Otherwise returns whether the faulting instruction can be
+/// restarted, which is never the case for an access the area's protection does not allow.
+pub fn handle_page_fault(process: &mut Process, address: usize, write: bool, protection_fault: bool) -> Option<bool> {
+  let area = *find_area(process, address)?;
+
+  // x86 can not take away read access from a present page, so PROT_WRITE and PROT_EXEC imply PROT_READ.
+  let allowed = if write {
+    area.protection & PROT_WRITE != 0
+  } else {
+    area.protection != PROT_NONE
+  };
+  if !allowed {
+    return Some(false);
+  }
+
+  let page_directory = process.address_space.as_mut()?.page_directory();
+  if protection_fault {
+    // A write to a private page that fork shared with another process.
+    return Some(write && virtual_memory::handle_copy_on_write(page_directory, address));
+  }
+
+  Some(swap::swap_in(page_directory, address) || fill_page(page_directory, &area, address))
+}
+
+/// Copies the parent's areas into the child for fork.
+/// The pages of private areas become copy-on-write, while the pages of shared areas stay shared.
+/// Returns false if there is not enough memory for the child's page tables. The caller then frees the child's
+/// areas with unmap_all.
+pub fn copy_areas(parent: &mut Process, child: &mut Process) -> bool {
+  let address_space = parent.address_space.as_mut().expect("copy_areas: no address space");
+  let child_address_space = child.address_space.as_mut().expect("copy_areas: no child address space");
+
+  for (index, entry) in parent.memory_areas.iter().enumerate() {
+    if let Some(area) = *entry {
+      area.dup_backing();
+      child.memory_areas[index] = Some(area);
+      if !address_space.share(child_address_space, area.start, area.end, !area.is_shared()) {
+        return false;
+      }
+    }
+  }
+  true
+}
+
+/// Removes every area of the process, when it exits or replaces its memory.
+pub fn unmap_all(process: &mut Process) {
+  let page_directory = process.address_space.as_mut().expect("unmap_all: no address space").page_directory();
+  for entry in process.memory_areas.iter_mut() {
+    if let Some(area) = entry.take() {
+      unmap_area(page_directory, &area);
+    }
+  }
+}
+
+/// Writes back a shared file area's dirty pages, then unmaps its pages and drops its file reference.
+fn unmap_area(page_directory: &mut PD, area: &VirtualMemoryArea) {
+  if area.is_shared() {
+    write_back(page_directory, area);
+  }
+  virtual_memory::release_user_pages(page_directory, area.start, area.end);
+  area.close_backing();
+}
+
+/// Writes the dirty pages of a shared file area to the file.
+fn write_back(page_directory: &mut PD, area: &VirtualMemoryArea) {
+  let (file, offset) = match area.backing {
+    Backing::File { file, offset } => (file, offset),
+    _ => {
+      return;
+    }
+  };
+  let inode = match unsafe { (*file).inode() } {
+    Some(inode) => inode,
+    None => {
+      return;
+    }
+  };
+
+  let mut address = area.start;
+  while address < area.end {
+    // Swapping loses the dirty bit, so a page read back from swap is always written.
+    let swapped = swap::swap_in(page_directory, address);
+    if let Some(page_table_entry) = walk_page_directory(page_directory, address, false) {
+      if page_table_entry.is_present() && (page_table_entry.is_dirty() || swapped) {
+        let page = map_physical_virtual(page_table_entry.address().as_usize());
+        let contents = unsafe { slice::from_raw_parts(page as *const u8, PAGE_SIZE) };
+        inode.write(offset + (address - area.start), contents);
+
+        page_table_entry.0 &= !PTFlags::D.bits();
+        unsafe {
+          tlb::flush(address);
+        }
+      }
+    }
+    address += PAGE_SIZE;
+  }
+}
+
+/// Maps a page at the page containing address, filled from the area's backing.
+/// A shared memory area maps the segment's own page, while the other areas get a new page.
+/// Returns false if there is no memory for the page or its page table.
+fn fill_page(page_directory: &mut PD, area: &VirtualMemoryArea, address: usize) -> bool {
+  let page_address = page_round_down(address);
+  let page = match area.backing {
+    Backing::SharedMemory { segment, offset } => {
+      shm::get_page(segment, (offset + (page_address - area.start)) / PAGE_SIZE)
+    }
+    _ => FREE_PAGE_LIST.alloc_page(PageOwner::UserPage)
+  };
+  let page = match page {
+    Some(page) => page,
+    None => {
+      return false;
+    }
+  };
+
+  match area.backing {
+    Backing::Anonymous => unsafe {
+      (page as *mut u8).write_bytes(0, PAGE_SIZE);
+    }
+    Backing::File { file, offset } => {
+      let contents = unsafe { slice::from_raw_parts_mut(page as *mut u8, PAGE_SIZE) };
+      contents.fill(0);
+      // The part of the page past the end of the file stays zeroed.
+      if let Some(inode) = unsafe { (*file).inode() } {
+        inode.read(offset + (page_address - area.start), contents);
+      }
+    }
+    Backing::SharedMemory { .. } => {}
+  }
+
+  if !virtual_memory::map_user_page(page_directory, page_address, page, area.permissions()) {
+    unsafe {
+      FREE_PAGE_LIST.remove_reference(page);
+    }
+    return false;
+  }
+  true
+}
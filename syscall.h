@@ -0,0 +1,25 @@
+// System call numbers. Keep in sync with src/syscall.rs.
+#define SYS_fork         1
+#define SYS_exit         2
+#define SYS_wait         3
+#define SYS_read         5
+#define SYS_kill         6
+#define SYS_exec         7
+#define SYS_dup         10
+#define SYS_getpid      11
+#define SYS_sbrk        12
+#define SYS_sleep       13
+#define SYS_uptime      14
+#define SYS_open        15
+#define SYS_write       16
+#define SYS_close       21
+#define SYS_mmap        22
+#define SYS_munmap      23
+#define SYS_mprotect    24
+#define SYS_shmget      25
+#define SYS_shmat       26
+#define SYS_shmdt       27
+#define SYS_shmctl      28
+#define SYS_setpriority 29
+#define SYS_getpriority 30
+#define SYS_settickets  31
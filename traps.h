@@ -0,0 +1,2 @@
+// The system call trap number. Keep in sync with src/traps.rs.
+#define T_SYSCALL       64
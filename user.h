@@ -0,0 +1,38 @@
+// System calls and library functions for user programs.
+
+// system calls
+int fork(void);
+int exit(int) __attribute__((noreturn));
+int wait(int*);
+int read(int, void*, int);
+int write(int, const void*, int);
+int close(int);
+int kill(int);
+int exec(char*, char**, char**);
+int open(const char*, int);
+int dup(int);
+int getpid(void);
+char* sbrk(int);
+int sleep(int);
+int uptime(void);
+void* mmap(void*, int, int, int, int, int);
+int munmap(void*, int);
+int mprotect(void*, int, int);
+int shmget(int, int, int);
+void* shmat(int, void*, int);
+int shmdt(void*);
+int shmctl(int, int, void*);
+int setpriority(int, int);
+int getpriority(int);
+int settickets(int);
+
+// ulib.c
+char* strcpy(char*, const char*);
+int strcmp(const char*, const char*);
+uint strlen(const char*);
+void* memset(void*, int, uint);
+void* memmove(void*, const void*, int);
+int memcmp(const void*, const void*, uint);
+
+// printf.c
+void printf(int, const char*, ...);